@@ -1,23 +1,36 @@
 use reqwest::Client;
-use std::fs;
+use simple_log::warn;
 use std::path::PathBuf;
-use std::time::SystemTime;
+use std::time::{Duration, SystemTime};
 
-use crate::client::{decrypt_tokens, encrypt_token, TokenResponse, Tokens};
-use crate::{MALClient, MALError};
+use crate::client::{TokenHook, AUTH_URL};
+use crate::store::{FileTokenStore, TokenStore, DEFAULT_PROFILE};
+use crate::{MALClient, MALError, Tokens};
 
+///Also available as `MALClient::builder()`
+///
 ///# Example
 ///```
 ///  use lib_mal::ClientBuilder;
 ///  fn example() {
-///     let client = ClientBuilder::new().secret("[YOUR_CLIENT_ID]".to_string()).access_token("exampleExAmPlE".to_string()).build_no_refresh();
+///     let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).access_token("exampleExAmPlE".to_string()).build_no_refresh();
 ///  }
 ///```
 pub struct ClientBuilder {
+    client_id: Option<String>,
     client_secret: Option<String>,
     dirs: Option<PathBuf>,
     access_token: Option<String>,
+    refresh_token: Option<String>,
+    timeout: Option<Duration>,
+    user_agent: Option<String>,
+    http_client: Option<Client>,
     caching: bool,
+    profile: Option<String>,
+    store: Option<Box<dyn TokenStore>>,
+    refresh_margin: Option<Duration>,
+    on_token_refresh: Option<TokenHook>,
+    pub(crate) auth_url: String,
 }
 
 #[allow(clippy::new_without_default)]
@@ -25,27 +38,62 @@ impl ClientBuilder {
     ///Creates a new ClientBuilder. All fields are set to None by default.
     pub fn new() -> Self {
         ClientBuilder {
+            client_id: None,
             client_secret: None,
             dirs: None,
             access_token: None,
+            refresh_token: None,
+            timeout: None,
+            user_agent: None,
+            http_client: None,
             caching: false,
+            profile: None,
+            store: None,
+            refresh_margin: None,
+            on_token_refresh: None,
+            auth_url: AUTH_URL.to_owned(),
         }
     }
 
-    /// Sets the client_secret
+    /// Sets the client_id
     /// # Example
     ///
     ///```
     /// # use lib_mal::ClientBuilder;
     /// # fn test() {
     ///     let client =
-    ///     ClientBuilder::new().secret("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
     ///
-    ///     let another_client = ClientBuilder::new().secret(None).build_no_refresh();
+    ///     let another_client = ClientBuilder::new().client_id(None).build_no_refresh();
     /// # }
     ///
     ///```
-    pub fn secret(mut self, secret: impl Into<Option<String>>) -> Self {
+    pub fn client_id(mut self, id: impl Into<Option<String>>) -> Self {
+        self.client_id = id.into();
+        self
+    }
+
+    /// Sets the client_id
+    #[deprecated(note = "this sets the client id, use `client_id` instead")]
+    pub fn secret(self, secret: impl Into<Option<String>>) -> Self {
+        self.client_id(secret)
+    }
+
+    /// Sets the client_secret, which only apps registered with the "web" app type have. It's sent
+    /// along with the client_id when logging in and refreshing tokens.
+    /// # Example
+    ///
+    ///```
+    /// # use lib_mal::ClientBuilder;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new()
+    ///         .client_id("[YOUR_CLIENT_ID]".to_string())
+    ///         .client_secret("[YOUR_CLIENT_SECRET]".to_string())
+    ///         .build_no_refresh();
+    /// # }
+    ///
+    ///```
+    pub fn client_secret(mut self, secret: impl Into<Option<String>>) -> Self {
         self.client_secret = secret.into();
         self
     }
@@ -80,6 +128,71 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets a refresh token stored elsewhere. Without an access token as well, one is fetched
+    /// with it on the first request, see `MALClient::with_refresh_token`
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new()
+    ///         .client_id("[YOUR_CLIENT_ID]".to_string())
+    ///         .refresh_token("[STORED_REFRESH_TOKEN]".to_string())
+    ///         .build_no_refresh();
+    /// # }
+    /// ```
+    pub fn refresh_token(mut self, token: impl Into<Option<String>>) -> Self {
+        self.refresh_token = token.into();
+        self
+    }
+
+    /// Sets how long a request to MAL can take before it fails, including reading the response.
+    /// There's no timeout by default.
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// use std::time::Duration;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new().timeout(Duration::from_secs(10)).build_no_refresh();
+    /// # }
+    /// ```
+    pub fn timeout(mut self, timeout: impl Into<Option<Duration>>) -> Self {
+        self.timeout = timeout.into();
+        self
+    }
+
+    /// Sets the `User-Agent` header sent with every request
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new().user_agent("my-app/1.0".to_string()).build_no_refresh();
+    /// # }
+    /// ```
+    pub fn user_agent(mut self, user_agent: impl Into<Option<String>>) -> Self {
+        self.user_agent = user_agent.into();
+        self
+    }
+
+    /// Sets the `reqwest::Client` used for every request, including logging in and refreshing
+    /// tokens. The timeout and user agent options are ignored when one is given, so set those on
+    /// the `reqwest::Client` instead.
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// # fn test() {
+    ///     let http = reqwest::Client::builder().pool_max_idle_per_host(2).build().unwrap();
+    ///     let client = ClientBuilder::new().http_client(http).build_no_refresh();
+    /// # }
+    /// ```
+    pub fn http_client(mut self, client: Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
     /// Sets wether or not the client should cache the tokens
     /// # Example
     ///
@@ -95,6 +208,75 @@ impl ClientBuilder {
         self
     }
 
+    /// Sets which user's tokens the client uses from the cache dir, so several accounts can share
+    /// it. Defaults to `"default"`, see `MALClient::switch_profile` to change it later.
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// use std::path::PathBuf;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new().caching(true).cache_dir(PathBuf::from("/some/dir")).profile("alice".to_string()).build_no_refresh();
+    /// # }
+    ///
+    /// ```
+    pub fn profile(mut self, name: impl Into<Option<String>>) -> Self {
+        self.profile = name.into();
+        self
+    }
+
+    /// Sets where the client keeps its tokens, instead of the encrypted file in the cache dir.
+    /// This also enables caching.
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// use lib_mal::store::FileTokenStore;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new().token_store(Box::new(FileTokenStore::new("/some/dir"))).build_no_refresh();
+    /// # }
+    ///
+    /// ```
+    pub fn token_store(mut self, store: Box<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Sets how long before the access token expires the client will refresh it. Defaults to 5
+    /// minutes
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// use std::time::Duration;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new().refresh_margin(Duration::from_secs(60)).build_no_refresh();
+    /// # }
+    ///
+    /// ```
+    pub fn refresh_margin(mut self, margin: impl Into<Option<Duration>>) -> Self {
+        self.refresh_margin = margin.into();
+        self
+    }
+
+    /// Sets a function that's called with the new tokens whenever the client gets them, including
+    /// the refresh done by `build_with_refresh`. See `MALClient::set_on_token_refresh`
+    /// # Example
+    ///
+    /// ```
+    /// # use lib_mal::ClientBuilder;
+    /// # fn test() {
+    ///     let client = ClientBuilder::new()
+    ///         .on_token_refresh(|tokens| println!("new refresh token: {}", tokens.refresh_token()))
+    ///         .build_no_refresh();
+    /// # }
+    ///
+    /// ```
+    pub fn on_token_refresh(mut self, hook: impl Fn(&Tokens) + Send + Sync + 'static) -> Self {
+        self.on_token_refresh = Some(Box::new(hook));
+        self
+    }
+
     /// Builds a `MALClient` without attempting to refresh the access token
     ///
     /// # Example
@@ -104,17 +286,47 @@ impl ClientBuilder {
     /// use std::path::PathBuf;
     /// fn example() {
     ///     let client =
-    ///     ClientBuilder::new().secret("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_no_refresh();
+    ///     ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_no_refresh();
     /// }
     pub fn build_no_refresh(self) -> MALClient {
-        MALClient::new(
-            self.client_secret.unwrap_or_default(),
+        let http = self.make_http_client().unwrap_or_else(|e| {
+            warn!(
+                "Ignoring the timeout and user agent, the HTTP client failed to build: {}",
+                e
+            );
+            Client::new()
+        });
+        let has_access_token = self.access_token.is_some();
+        let mut client = MALClient::new(
+            self.client_id.unwrap_or_default(),
             self.dirs.unwrap_or_default(),
             self.access_token.unwrap_or_default(),
-            Client::new(),
-            self.caching,
+            http,
+            false,
             false,
-        )
+        );
+        if let Some(token) = self.refresh_token {
+            *client.refresh_token.get_mut().unwrap() = token.into();
+            if !has_access_token {
+                //no access token yet, so treat it as already expired
+                *client.expires_at.get_mut().unwrap() = Some(SystemTime::UNIX_EPOCH);
+            }
+        }
+        client.auth_url = self.auth_url;
+        //set first so the token cache is encrypted with it and in the right place
+        client.set_client_secret(self.client_secret);
+        if let Some(profile) = self.profile {
+            client.profile = profile;
+        }
+        client.set_caching(self.caching);
+        if let Some(store) = self.store {
+            client.set_token_store(store);
+        }
+        if let Some(margin) = self.refresh_margin {
+            client.set_refresh_margin(margin);
+        }
+        client.on_token_refresh = self.on_token_refresh;
+        client
     }
 
     /// Builds a `MALClient` after attempting to refresh the access token from cache
@@ -127,90 +339,124 @@ impl ClientBuilder {
     /// use std::path::PathBuf;
     /// async fn example() -> Result<(), MALError> {
     ///     let client =
-    ///     ClientBuilder::new().secret("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
+    ///     ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
     ///
     ///     Ok(())
     /// }
     pub async fn build_with_refresh(self) -> Result<MALClient, MALError> {
-        let client = reqwest::Client::new();
-        let mut will_cache = self.caching;
-        let mut n_a = false;
-
-        let dir = if let Some(d) = self.dirs {
-            d
-        } else {
-            will_cache = false;
-            PathBuf::new()
+        let client = self.make_http_client()?;
+        let dir = self.dirs.unwrap_or_default();
+        let profile = self.profile.unwrap_or_else(|| DEFAULT_PROFILE.to_owned());
+        let store = match self.store {
+            Some(store) => Some(store),
+            //the file cache needs somewhere to live
+            None if self.caching && dir != PathBuf::new() => {
+                let store = match &self.client_secret {
+                    Some(secret) => FileTokenStore::with_secret(dir.clone(), secret),
+                    None => FileTokenStore::new(dir.clone()),
+                };
+                Some(Box::new(store.profile(profile.clone())) as Box<dyn TokenStore>)
+            }
+            None => None,
         };
 
-        let mut token = String::new();
-        if will_cache && dir.join("tokens").exists() {
-            if let Ok(tokens) = fs::read(dir.join("tokens")) {
-                let mut tok: Tokens = decrypt_tokens(&tokens).unwrap();
-                if let Ok(n) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
-                    if n.as_secs() - tok.today >= tok.expires_in as u64 {
-                        let params = [
-                            ("grant_type", "refresh_token"),
-                            ("refesh_token", &tok.refresh_token),
-                        ];
-                        let res = client
-                            .post("https://myanimelist.net/v1/oauth2/token")
-                            .form(&params)
-                            .send()
-                            .await;
-                        if let Err(e) = res {
-                            return Err(MALError::new(
-                                "Unable to refresh token",
-                                e.to_string().as_str(),
-                                None,
-                            ));
-                        }
-                        let new_toks = serde_json::from_str::<TokenResponse>(
-                            &res.unwrap().text().await.unwrap(),
-                        );
-                        if let Err(e) = new_toks {
-                            return Err(MALError::new(
-                                "Unable to parse token reponse",
-                                e.to_string().as_str(),
-                                None,
-                            ));
-                        }
-                        let new_toks = new_toks.unwrap();
-                        token = new_toks.access_token.clone();
-                        tok = Tokens {
-                            access_token: new_toks.access_token,
-                            refresh_token: new_toks.refresh_token,
-                            expires_in: new_toks.expires_in,
-                            today: SystemTime::now()
-                                .duration_since(SystemTime::UNIX_EPOCH)
-                                .unwrap()
-                                .as_secs(),
-                        };
-
-                        if let Err(e) = fs::write(dir.join("tokens"), encrypt_token(tok)) {
-                            return Err(MALError::new(
-                                "Unable to write tokens to cache",
-                                e.to_string().as_str(),
-                                None,
-                            ));
-                        }
-                    } else {
-                        token = tok.access_token;
-                    }
+        let mut cached = None;
+        let mut cache_error = None;
+        if let Some(store) = &store {
+            //a cache that can't be read just means the user has to log in again
+            match store.load().await {
+                Ok(tok) => cached = tok,
+                Err(e) => {
+                    warn!("Ignoring unusable token cache: {}", e);
+                    cache_error = Some(e);
                 }
             }
-        } else {
-            will_cache = self.caching;
-            n_a = true;
         }
 
-        Ok(MALClient::new(
-            self.client_secret.unwrap_or_default(),
+        let mut client = MALClient::new(
+            self.client_id.unwrap_or_default(),
             dir,
-            token,
+            cached
+                .as_ref()
+                .map(|t| t.access_token.to_string())
+                .unwrap_or_default(),
             client,
-            will_cache,
-            n_a,
-        ))
+            false,
+            cached.is_none(),
+        );
+        client.set_client_secret(self.client_secret);
+        client.profile = profile;
+        if let Some(store) = store {
+            client.set_token_store(store);
+        } else {
+            client.set_caching(self.caching);
+        }
+        client.auth_url = self.auth_url;
+        if let Some(margin) = self.refresh_margin {
+            client.set_refresh_margin(margin);
+        }
+        client.on_token_refresh = self.on_token_refresh;
+        client.cache_error = cache_error;
+        if let Some(tok) = cached {
+            let expires_at = tok.expires_at();
+            *client.refresh_token.get_mut().unwrap() = tok.refresh_token;
+            *client.expires_at.get_mut().unwrap() = Some(expires_at);
+            if SystemTime::now() >= expires_at {
+                client.refresh().await?;
+            }
+        }
+        Ok(client)
+    }
+
+    /// Builds a `MALClient`. With an access or refresh token the client uses those, like
+    /// `build_no_refresh`. Otherwise it loads the tokens from the cache and refreshes them if
+    /// they've expired, like `build_with_refresh`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use lib_mal::{MALClient, MALError};
+    /// use std::path::PathBuf;
+    /// use std::time::Duration;
+    /// async fn example() -> Result<(), MALError> {
+    ///     let client = MALClient::builder()
+    ///         .client_id("[YOUR_CLIENT_ID]".to_string())
+    ///         .caching(true)
+    ///         .cache_dir(PathBuf::from("/some/dir"))
+    ///         .timeout(Duration::from_secs(10))
+    ///         .user_agent("my-app/1.0".to_string())
+    ///         .build()
+    ///         .await?;
+    ///
+    ///     Ok(())
+    /// }
+    /// ```
+    pub async fn build(self) -> Result<MALClient, MALError> {
+        if self.access_token.is_some() || self.refresh_token.is_some() {
+            //fail on a bad user agent here instead of ignoring it
+            if self.http_client.is_none() {
+                self.make_http_client()?;
+            }
+            Ok(self.build_no_refresh())
+        } else {
+            self.build_with_refresh().await
+        }
+    }
+
+    fn make_http_client(&self) -> Result<Client, MALError> {
+        if let Some(client) = &self.http_client {
+            if self.timeout.is_some() || self.user_agent.is_some() {
+                warn!("Ignoring the timeout and user agent, an HTTP client was given");
+            }
+            return Ok(client.clone());
+        }
+        let mut builder = Client::builder();
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+        if let Some(user_agent) = &self.user_agent {
+            builder = builder.user_agent(user_agent);
+        }
+        Ok(builder.build()?)
     }
 }
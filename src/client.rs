@@ -1,21 +1,42 @@
+#[cfg(feature = "chrono")]
+use crate::model::ListNode;
 use crate::model::{
     fields::AnimeFields,
-    options::{Params, RankingType, Season, StatusUpdate},
-    AnimeDetails, AnimeList, ForumBoards, ForumTopics, ListStatus, TopicDetails, User,
+    options::{Params, RankingType, Season, StatusUpdate, WatchStatus},
+    AnimeDetails, AnimeId, AnimeList, BoardId, ForumBoards, ForumTopics, ListStatus, SubboardId,
+    TopicDetails, TopicId, User,
 };
-use rand::random;
+use futures::{stream, Future, StreamExt};
+use rand::{rngs::OsRng, RngCore};
 use reqwest::Client;
-use reqwest::{Method, StatusCode};
+use reqwest::{Method, RequestBuilder, StatusCode};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 #[allow(unused_imports)]
-use simple_log::{debug, info};
-use std::{fs::File, io::Write, path::PathBuf, str, time::SystemTime};
-use tiny_http::{Response, Server};
+use simple_log::{debug, info, warn};
+use std::{
+    fmt::Debug,
+    path::PathBuf,
+    str,
+    sync::RwLock,
+    time::{Duration, SystemTime},
+};
+use tiny_http::Server;
+use tokio::sync::{oneshot, Mutex};
 
-use crate::MALError;
+use crate::error::ApiError;
+use crate::listener::{parse_callback, redirect_uri_with_scheme, CallbackListener, CancelHandle};
+use crate::store::{self, FileTokenStore, TokenStore, DEFAULT_PROFILE};
+use crate::{ClientBuilder, MALError};
+use zeroize::Zeroizing;
 
-use aes_gcm::aead::{Aead, NewAead};
-use aes_gcm::{Aes256Gcm, Key, Nonce};
+const API_URL: &str = "https://api.myanimelist.net/v2";
+pub(crate) const AUTH_URL: &str = "https://myanimelist.net/v1/oauth2";
+const REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+const BACKOFF_START: Duration = Duration::from_millis(500);
+const BACKOFF_TRIES: u32 = 5;
+///Shown in place of tokens and secrets in `Debug` output and errors
+const REDACTED: &str = "[redacted]";
 
 ///Exposes all of the API functions for the [MyAnimeList API](https://myanimelist.net/apiconfig/references/api/v2)
 ///
@@ -26,7 +47,7 @@ use aes_gcm::{Aes256Gcm, Key, Nonce};
 /// use lib_mal::ClientBuilder;
 /// # use lib_mal::MALError;
 /// # async fn test() -> Result<(), MALError> {
-/// let client = ClientBuilder::new().secret("[YOUR_SECRET_HERE]".to_string()).build_no_refresh();
+/// let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
 /// //--do authorization stuff before accessing the functions--//
 ///
 /// //Gets the details with all fields for Mobile Suit Gundam
@@ -40,60 +61,275 @@ use aes_gcm::{Aes256Gcm, Key, Nonce};
 /// # }
 ///```
 pub struct MALClient {
-    client_secret: String,
+    client_id: String,
+    client_secret: Option<Zeroizing<String>>,
     dirs: PathBuf,
-    access_token: String,
+    pub(crate) profile: String,
+    store: Option<Box<dyn TokenStore>>,
+    access_token: RwLock<Zeroizing<String>>,
+    pub(crate) refresh_token: RwLock<Zeroizing<String>>,
+    pub(crate) expires_at: RwLock<Option<SystemTime>>,
+    refresh_margin: Duration,
+    refresh_lock: Mutex<()>,
     client: reqwest::Client,
-    caching: bool,
     pub need_auth: bool,
+    pub(crate) api_url: String,
+    pub(crate) auth_url: String,
+    auth_response: Option<(String, String)>,
+    pub(crate) on_token_refresh: Option<TokenHook>,
+    pub(crate) cache_error: Option<MALError>,
+    save_error: RwLock<Option<MALError>>,
+    #[cfg(feature = "https-callback")]
+    callback_tls: Option<tiny_http::SslConfig>,
 }
 
+///Called with the new tokens whenever the client gets them
+pub(crate) type TokenHook = Box<dyn Fn(&Tokens) + Send + Sync>;
+
 impl MALClient {
     pub fn new(
-        client_secret: String,
+        client_id: String,
         dirs: PathBuf,
         access_token: String,
         client: Client,
         caching: bool,
         need_auth: bool,
     ) -> Self {
+        let store = if caching {
+            Some(Box::new(FileTokenStore::new(dirs.clone())) as Box<dyn TokenStore>)
+        } else {
+            None
+        };
         MALClient {
-            client_secret,
+            client_id,
+            client_secret: None,
             dirs,
-            access_token,
-            caching,
+            profile: DEFAULT_PROFILE.to_owned(),
+            store,
+            access_token: RwLock::new(access_token.into()),
+            refresh_token: RwLock::default(),
+            expires_at: RwLock::default(),
+            refresh_margin: REFRESH_MARGIN,
+            refresh_lock: Mutex::default(),
             need_auth,
             client,
+            api_url: API_URL.to_owned(),
+            auth_url: AUTH_URL.to_owned(),
+            auth_response: None,
+            on_token_refresh: None,
+            cache_error: None,
+            save_error: RwLock::default(),
+            #[cfg(feature = "https-callback")]
+            callback_tls: None,
         }
     }
 
+    ///Creates a `ClientBuilder` for setting up a client
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let client = MALClient::builder()
+    ///         .client_id("[YOUR_CLIENT_ID]".to_string())
+    ///         .refresh_token("[STORED_REFRESH_TOKEN]".to_string())
+    ///         .build()
+    ///         .await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub fn builder() -> ClientBuilder {
+        ClientBuilder::new()
+    }
+
     ///Creates a client using provided token. Caching is disable by default.
     ///
     ///A client created this way can't authenticate the user if needed because it lacks a
-    ///`client_secret`
+    ///`client_id`
     pub fn with_access_token(token: &str) -> Self {
+        ClientBuilder::new()
+            .access_token(token.to_owned())
+            .build_no_refresh()
+    }
+
+    ///Creates a client from a refresh token stored elsewhere. Caching is disabled by default.
+    ///
+    ///The access token is fetched with the refresh token on the first request, or earlier by
+    ///calling `refresh`
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let client = MALClient::with_refresh_token("[YOUR_CLIENT_ID]", "[STORED_REFRESH_TOKEN]");
+    ///     client.refresh().await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub fn with_refresh_token(client_id: &str, refresh_token: &str) -> Self {
+        ClientBuilder::new()
+            .client_id(client_id.to_owned())
+            .refresh_token(refresh_token.to_owned())
+            .build_no_refresh()
+    }
+
+    ///Creates a client from tokens saved with `tokens`. Caching is disabled by default.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, Tokens};
+    /// # fn load_tokens() -> Tokens { unimplemented!() }
+    /// # fn test() {
+    ///     let tokens: Tokens = load_tokens();
+    ///     let client = MALClient::from_tokens("[YOUR_CLIENT_ID]", tokens);
+    /// # }
+    ///```
+    pub fn from_tokens(client_id: &str, tokens: Tokens) -> Self {
+        let expires_at = tokens.expires_at();
         MALClient {
-            client_secret: String::new(),
-            need_auth: false,
-            dirs: PathBuf::new(),
-            access_token: token.to_owned(),
-            client: reqwest::Client::new(),
-            caching: false,
+            client_id: client_id.to_owned(),
+            refresh_token: RwLock::new(tokens.refresh_token),
+            expires_at: RwLock::new(Some(expires_at)),
+            ..MALClient::with_access_token(&tokens.access_token)
+        }
+    }
+
+    ///Returns a copy of the client's current tokens so they can be stored. Returns `None` if the
+    ///client doesn't have a refresh token and expiry, such as one created with `with_access_token`.
+    ///
+    ///The tokens change whenever the client refreshes them, so save them again after refreshing.
+    pub fn tokens(&self) -> Option<Tokens> {
+        let refresh_token = self.refresh_token.read().unwrap().to_string();
+        let expires_at = (*self.expires_at.read().unwrap())?;
+        if refresh_token.is_empty() {
+            return None;
         }
+        Some(Tokens::new(
+            self.get_access_token(),
+            refresh_token,
+            expires_at,
+        ))
     }
 
-    ///Sets the directory the client will use for the token cache
+    ///Sets the client secret sent when logging in and refreshing tokens. Only apps registered
+    ///with the "web" app type have one.
+    pub fn set_client_secret(&mut self, secret: impl Into<Option<String>>) {
+        self.client_secret = secret.into().map(Zeroizing::new);
+    }
+
+    ///Sets the directory the client will use for the token cache. If caching is enabled this
+    ///replaces any store set with `set_token_store`.
     pub fn set_cache_dir(&mut self, dir: PathBuf) {
         self.dirs = dir;
+        if self.store.is_some() {
+            self.store = Some(self.file_store());
+        }
     }
 
     ///Sets wether the client will cache or not
     pub fn set_caching(&mut self, caching: bool) {
-        self.caching = caching;
+        if !caching {
+            self.store = None;
+        } else if self.store.is_none() {
+            self.store = Some(self.file_store());
+        }
+    }
+
+    ///The current profile's token file in the cache dir, encrypted with the client secret if
+    ///there is one
+    fn file_store(&self) -> Box<dyn TokenStore> {
+        let store = match &self.client_secret {
+            Some(secret) => FileTokenStore::with_secret(self.dirs.clone(), secret),
+            None => FileTokenStore::new(self.dirs.clone()),
+        };
+        Box::new(store.profile(self.profile.clone()))
+    }
+
+    ///Sets where the client saves its tokens, instead of the encrypted file in the cache dir.
+    ///This also enables caching.
+    pub fn set_token_store(&mut self, store: Box<dyn TokenStore>) {
+        self.store = Some(store);
+    }
+
+    ///Sets a function that's called with the new tokens whenever they change, after logging in
+    ///and every time the client refreshes them, for applications that store the tokens
+    ///themselves. It runs before the tokens are saved to the token store.
+    ///
+    ///# Example
+    ///
+    ///```
+    /// # use lib_mal::MALClient;
+    /// # fn test() {
+    ///     let mut client = MALClient::with_access_token("[YOUR_TOKEN_HERE]");
+    ///     client.set_on_token_refresh(|tokens| {
+    ///         println!("new refresh token: {}", tokens.refresh_token());
+    ///     });
+    /// # }
+    ///```
+    pub fn set_on_token_refresh(&mut self, hook: impl Fn(&Tokens) + Send + Sync + 'static) {
+        self.on_token_refresh = Some(Box::new(hook));
+    }
+
+    ///Sets how long before the access token expires the client will refresh it. Defaults to 5
+    ///minutes
+    pub fn set_refresh_margin(&mut self, margin: Duration) {
+        self.refresh_margin = margin;
+    }
+
+    ///Sets the page shown in the user's browser once `auth` receives the callback, instead of the
+    ///default plain text message
+    ///
+    ///# Example
+    ///
+    ///```
+    /// # use lib_mal::MALClient;
+    /// # fn test() {
+    ///     let mut client = MALClient::with_access_token("[YOUR_TOKEN_HERE]");
+    ///     client.set_auth_response(
+    ///         "<h1>Logged in!</h1><script>window.close()</script>",
+    ///         "text/html; charset=utf-8",
+    ///     );
+    /// # }
+    ///```
+    pub fn set_auth_response(&mut self, body: impl Into<String>, content_type: &str) {
+        self.auth_response = Some((body.into(), content_type.to_owned()));
+    }
+
+    ///Makes `auth` serve the callback over https using the PEM encoded `certificate` and
+    ///`private_key`, for redirect URIs like `https://localhost:8443`. Without this the callback
+    ///is served over plain http.
+    ///
+    ///Requires the `https-callback` feature
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::MALClient;
+    /// # fn test() {
+    ///     let mut client = MALClient::with_access_token("[YOUR_TOKEN_HERE]");
+    ///     client.set_callback_tls(
+    ///         std::fs::read("localhost.pem").unwrap(),
+    ///         std::fs::read("localhost-key.pem").unwrap(),
+    ///     );
+    /// # }
+    ///```
+    #[cfg(feature = "https-callback")]
+    pub fn set_callback_tls(&mut self, certificate: Vec<u8>, private_key: Vec<u8>) {
+        self.callback_tls = Some(tiny_http::SslConfig {
+            certificate,
+            private_key,
+        });
     }
 
     ///Returns the auth URL and code challenge which will be needed to authorize the user.
     ///
+    ///`redirect_uri` has to exactly match one of the URIs registered with the MAL api, and the
+    ///same URI has to be passed to `auth` afterwards. A URI without a scheme is treated as
+    ///`http://`.
+    ///
     ///# Example
     ///
     ///```no_run
@@ -102,28 +338,42 @@ impl MALClient {
     ///     # async fn test() -> Result<(), MALError> {
     ///     let redirect_uri = "http://localhost:2525";//<-- example uri
     ///     let mut client =
-    ///     ClientBuilder::new().secret("[YOUR_SECRET_HERE]".to_string()).build_no_refresh();
-    ///     let (url, challenge, state) = client.get_auth_parts();
+    ///     ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
     ///     println!("Go here to log in: {}", url);
-    ///     client.auth(&redirect_uri, &challenge, &state).await?;
+    ///     client.auth(redirect_uri, &challenge, &state).await?;
     ///     # Ok(())
     ///     # }
     ///```
-    pub fn get_auth_parts(&self) -> (String, String, String) {
+    pub fn get_auth_parts(&self, redirect_uri: &str) -> (String, String, String) {
         let verifier = pkce::code_verifier(128);
         let challenge = pkce::code_challenge(&verifier);
-        let state = format!("bruh{}", random::<u8>());
-        let url = format!("https://myanimelist.net/v1/oauth2/authorize?response_type=code&client_id={}&code_challenge={}&state={}", self.client_secret, challenge, state, );
+        let state = random_state();
+        let url = reqwest::Url::parse_with_params(
+            &format!("{}/authorize", self.auth_url),
+            &[
+                ("response_type", "code"),
+                ("client_id", self.client_id.as_str()),
+                ("code_challenge", challenge.as_str()),
+                //the challenge doubles as the verifier when exchanging the code
+                ("code_challenge_method", "plain"),
+                ("state", state.as_str()),
+                (
+                    "redirect_uri",
+                    redirect_uri_with_scheme(redirect_uri).as_str(),
+                ),
+            ],
+        )
+        .map(String::from)
+        .unwrap_or_default();
         (url, challenge, state)
     }
 
-    ///Listens for the OAuth2 callback from MAL on `callback_url`, which is the redirect_uri
-    ///registered when obtaining the API token from MAL. Only HTTP URIs are supported right now.
-    ///
-    ///# NOTE
+    ///Listens for the OAuth2 callback from MAL on `redirect_uri`, which has to be the same
+    ///redirect_uri that was passed to `get_auth_parts`. HTTPS URIs need `set_callback_tls`.
     ///
-    ///For now only applications with a single registered URI are supported, having more than one
-    ///seems to cause issues with the MAL api itself
+    ///Returns a `bind_failed` error if the listener can't be started on the redirect_uri's
+    ///address, for example because the port is already in use
     ///
     ///# Example
     ///
@@ -133,154 +383,621 @@ impl MALClient {
     ///     # async fn test() -> Result<(), MALError> {
     ///     let redirect_uri = "localhost:2525";//<-- example uri,
     ///     //appears as "http://localhost:2525" in the API settings
-    ///     let mut client = ClientBuilder::new().secret("[YOUR_SECRET_HERE]".to_string()).build_no_refresh();
-    ///     let (url, challenge, state) = client.get_auth_parts();
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
     ///     println!("Go here to log in: {}", url);
-    ///     client.auth(&redirect_uri, &challenge, &state).await?;
+    ///     client.auth(redirect_uri, &challenge, &state).await?;
     ///     # Ok(())
     ///     # }
     ///
     ///```
     pub async fn auth(
         &mut self,
-        callback_url: &str,
+        redirect_uri: &str,
+        challenge: &str,
+        state: &str,
+    ) -> Result<(), MALError> {
+        self.listen_for_auth(redirect_uri, challenge, state, None, None)
+            .await
+    }
+
+    ///Same as `auth`, but gives up with an `auth_timeout` error if the callback doesn't arrive
+    ///within `timeout`, for example because the user closed the browser tab. The listener is shut
+    ///down before returning.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     use std::time::Duration;
+    ///     # use lib_mal::MALError;
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let redirect_uri = "localhost:2525";
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
+    ///     println!("Go here to log in: {}", url);
+    ///     client.auth_with_timeout(redirect_uri, &challenge, &state, Duration::from_secs(300)).await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    pub async fn auth_with_timeout(
+        &mut self,
+        redirect_uri: &str,
+        challenge: &str,
+        state: &str,
+        timeout: Duration,
+    ) -> Result<(), MALError> {
+        self.listen_for_auth(redirect_uri, challenge, state, Some(timeout), None)
+            .await
+    }
+
+    ///Same as `auth`, but also returns a `CancelHandle` that stops waiting for the callback. The
+    ///future then resolves with an `auth_cancelled` error once the listener has been shut down.
+    ///
+    ///Dropping the future also shuts down the listener.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     # use lib_mal::MALError;
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let redirect_uri = "localhost:2525";
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
+    ///     println!("Go here to log in: {}", url);
+    ///     let (login, cancel) = client.auth_cancellable(redirect_uri, &challenge, &state);
+    ///     //hand `cancel` to the "Cancel login" button
+    ///     # cancel.cancel();
+    ///     login.await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    pub fn auth_cancellable<'a>(
+        &'a mut self,
+        redirect_uri: &'a str,
+        challenge: &'a str,
+        state: &'a str,
+    ) -> (
+        impl Future<Output = Result<(), MALError>> + Send + 'a,
+        CancelHandle,
+    ) {
+        let (handle, cancel) = CancelHandle::new();
+        let login = self.listen_for_auth(redirect_uri, challenge, state, None, Some(cancel));
+        (login, handle)
+    }
+
+    async fn listen_for_auth(
+        &mut self,
+        redirect_uri: &str,
+        challenge: &str,
+        state: &str,
+        timeout: Option<Duration>,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<(), MALError> {
+        let listener = self.start_listener(redirect_uri)?;
+        self.finish_auth(listener, challenge, state, timeout, cancel)
+            .await
+    }
+
+    ///Starts listening for the OAuth2 callback on `redirect_uri` without waiting for it yet. A
+    ///port of 0 lets the OS pick a free port, and `CallbackListener::redirect_uri` has the
+    ///actual redirect_uri to pass to `get_auth_parts`.
+    ///
+    ///Returns a `bind_failed` error if the listener can't be started
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     use std::time::Duration;
+    ///     # use lib_mal::MALError;
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let listener = client.start_listener("localhost:0")?;
+    ///     println!("Listening on port {}", listener.local_addr().port());
+    ///     let (url, challenge, state) = client.get_auth_parts(listener.redirect_uri());
+    ///     println!("Go here to log in: {}", url);
+    ///     client.wait_for_callback(listener, &challenge, &state, Duration::from_secs(300)).await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    pub fn start_listener(&self, redirect_uri: &str) -> Result<CallbackListener, MALError> {
+        CallbackListener::bind(redirect_uri, |addr| {
+            #[cfg(feature = "https-callback")]
+            if let Some(tls) = &self.callback_tls {
+                return Server::https(addr, tls.clone());
+            }
+            Server::http(addr)
+        })
+    }
+
+    ///Waits for the OAuth2 callback on a listener from `start_listener` and finishes logging in
+    ///like `auth` does. Gives up with an `auth_timeout` error after `timeout`, if there is one.
+    pub async fn wait_for_callback(
+        &mut self,
+        listener: CallbackListener,
+        challenge: &str,
+        state: &str,
+        timeout: impl Into<Option<Duration>>,
+    ) -> Result<(), MALError> {
+        self.finish_auth(listener, challenge, state, timeout.into(), None)
+            .await
+    }
+
+    async fn finish_auth(
+        &mut self,
+        listener: CallbackListener,
+        challenge: &str,
+        state: &str,
+        timeout: Option<Duration>,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<(), MALError> {
+        let redirect_uri = listener.redirect_uri().to_owned();
+        let code = listener
+            .wait(state, self.auth_response.clone(), timeout, cancel)
+            .await?;
+        self.auth_with_code(&code, challenge, &redirect_uri).await
+    }
+
+    ///Opens `auth_url` in the user's browser. Returns a `browser_unavailable` error with the URL in
+    ///`info` if no browser could be launched, so it can be shown to the user instead.
+    ///
+    ///Requires the `open-browser` feature
+    #[cfg(feature = "open-browser")]
+    pub fn open_auth_page(&self, auth_url: &str) -> Result<(), MALError> {
+        webbrowser::open(auth_url).map_err(|e| {
+            MALError::new(
+                &format!("Unable to open a browser: {}", e),
+                "browser_unavailable",
+                auth_url.to_owned(),
+            )
+        })
+    }
+
+    ///Logs the user in with a single call by opening the auth page in their browser, listening
+    ///on `redirect_uri` and completing the handshake. If no browser can be opened `fallback` is
+    ///called with the auth URL so it can be shown to the user instead.
+    ///
+    ///Requires the `open-browser` feature
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     use std::time::Duration;
+    ///     # use lib_mal::MALError;
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     client
+    ///         .auth_interactive("localhost:2525", Duration::from_secs(300), |url| {
+    ///             println!("Go here to log in: {}", url)
+    ///         })
+    ///         .await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    #[cfg(feature = "open-browser")]
+    pub async fn auth_interactive(
+        &mut self,
+        redirect_uri: &str,
+        timeout: impl Into<Option<Duration>>,
+        fallback: impl FnOnce(&str),
+    ) -> Result<(), MALError> {
+        let listener = self.start_listener(redirect_uri)?;
+        let (url, challenge, state) = self.get_auth_parts(listener.redirect_uri());
+        if let Err(e) = self.open_auth_page(&url) {
+            debug!("{}", e);
+            fallback(&url);
+        }
+        self.wait_for_callback(listener, &challenge, &state, timeout)
+            .await
+    }
+
+    ///Exchanges an authorization code for tokens and stores them the same way `auth` does. This is
+    ///the second half of `auth`, for applications that get the code some other way than the
+    ///built in listener.
+    ///
+    ///`verifier` is the challenge returned by `get_auth_parts`, and `redirect_uri` has to be the
+    ///same one that was passed to it.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     # use lib_mal::MALError;
+    ///     # fn wait_for_code() -> String { unimplemented!() }
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let redirect_uri = "https://example.com/mal-callback";
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
+    ///     println!("Go here to log in: {}", url);
+    ///     let code = wait_for_code();
+    ///     client.auth_with_code(&code, &challenge, redirect_uri).await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    pub async fn auth_with_code(
+        &mut self,
+        code: &str,
+        verifier: &str,
+        redirect_uri: &str,
+    ) -> Result<(), MALError> {
+        let redirect_uri = redirect_uri_with_scheme(redirect_uri);
+        let mut params = vec![
+            ("client_id", self.client_id.as_str()),
+            ("grant_type", "authorization_code"),
+            ("code_verifier", verifier),
+            ("code", code),
+            ("redirect_uri", redirect_uri.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            params.push(("client_secret", secret));
+        }
+        let res = self
+            .client
+            .request(Method::POST, format!("{}/token", self.auth_url))
+            .form(&params)
+            .send()
+            .await?;
+        let text = read_body(res).await?;
+        self.save_tokens(parse_token_response(&text)?).await;
+        self.need_auth = false;
+        Ok(())
+    }
+
+    ///Finishes logging in from the URL MAL redirected the user to, or just the code in it, for
+    ///applications that can't run a listener. The user can log in on any device and paste the
+    ///URL from their address bar back into the application.
+    ///
+    ///Returns a `state_mismatch` error if the URL is for a different login attempt.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    ///     use lib_mal::ClientBuilder;
+    ///     # use lib_mal::MALError;
+    ///     # async fn test() -> Result<(), MALError> {
+    ///     let redirect_uri = "http://localhost:2525";
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).build_no_refresh();
+    ///     let (url, challenge, state) = client.get_auth_parts(redirect_uri);
+    ///     println!("Go here to log in, then paste the URL you end up on: {}", url);
+    ///     let mut pasted = String::new();
+    ///     std::io::stdin().read_line(&mut pasted).unwrap();
+    ///     client.auth_from_redirect_url(&pasted, redirect_uri, &challenge, &state).await?;
+    ///     # Ok(())
+    ///     # }
+    ///```
+    pub async fn auth_from_redirect_url(
+        &mut self,
+        pasted: &str,
+        redirect_uri: &str,
         challenge: &str,
         state: &str,
     ) -> Result<(), MALError> {
-        let mut code = "".to_owned();
-        let url = if callback_url.contains("http") {
-            //server won't work if the url has the protocol in it
-            callback_url
-                .trim_start_matches("http://")
-                .trim_start_matches("https://")
+        let pasted = pasted.trim();
+        let code = if pasted.contains('?') {
+            parse_callback(pasted, state)?
         } else {
-            callback_url
+            pasted.to_owned()
         };
+        self.auth_with_code(&code, challenge, redirect_uri).await
+    }
 
-        let server = Server::http(url).unwrap();
-        for i in server.incoming_requests() {
-            if !i.url().contains(&format!("state={}", state)) {
-                //if the state doesn't match, discard this response
-                continue;
+    ///Exchanges the refresh token for a new access token, updating the token cache if caching is
+    ///enabled.
+    ///
+    ///Clients that don't have a refresh token, like ones created with `with_access_token`, return
+    ///a `no_refresh_token` error
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{ClientBuilder, MALError};
+    /// # use std::path::PathBuf;
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
+    ///     //some time later...
+    ///     client.refresh().await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn refresh(&self) -> Result<(), MALError> {
+        let refresh_token = self.refresh_token.read().unwrap().clone();
+        if refresh_token.is_empty() {
+            return Err(MALError::auth(
+                "no_refresh_token",
+                "No refresh token available",
+            ));
+        }
+        let mut params = vec![
+            ("grant_type", "refresh_token"),
+            ("refresh_token", refresh_token.as_str()),
+            ("client_id", self.client_id.as_str()),
+        ];
+        if let Some(secret) = &self.client_secret {
+            params.push(("client_secret", secret));
+        }
+        let res = self
+            .client
+            .post(format!("{}/token", self.auth_url))
+            .form(&params)
+            .send()
+            .await?;
+        let text = read_body(res).await?;
+        self.save_tokens(parse_token_response(&text)?).await;
+        Ok(())
+    }
+
+    ///Signs the user out by forgetting the tokens and deleting the token cache if caching is
+    ///enabled. Afterwards `need_auth` is true and the user will have to log in again.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{ClientBuilder, MALError};
+    /// # use std::path::PathBuf;
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
+    ///     client.logout().await?;
+    ///     assert!(client.need_auth);
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn logout(&mut self) -> Result<(), MALError> {
+        self.access_token.get_mut().unwrap().clear();
+        self.refresh_token.get_mut().unwrap().clear();
+        *self.expires_at.get_mut().unwrap() = None;
+        self.need_auth = true;
+        if let Some(store) = &self.store {
+            store.clear().await?;
+        }
+        Ok(())
+    }
+
+    ///Why the token cache was ignored when the client was built with
+    ///`ClientBuilder::build_with_refresh`, for example because the file is corrupt. The client
+    ///needs auth when this is set, and the bad cache is replaced once the user logs in again.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{ClientBuilder, MALError};
+    /// # use std::path::PathBuf;
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
+    ///     if let Some(e) = client.cache_error() {
+    ///         println!("Your saved login couldn't be read ({}), please log in again", e);
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub fn cache_error(&self) -> Option<&MALError> {
+        self.cache_error.as_ref()
+    }
+
+    ///Why the token store couldn't save the tokens the last time they changed, for example
+    ///because the keyring is locked. Logging in and refreshing still succeed when saving fails,
+    ///but the user will have to log in again next time. Cleared by taking it or by the next
+    ///successful save.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test(client: MALClient) -> Result<(), MALError> {
+    ///     client.refresh().await?;
+    ///     if let Some(e) = client.take_save_error() {
+    ///         println!("Your login couldn't be saved ({}), you'll need to log in again", e);
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub fn take_save_error(&self) -> Option<MALError> {
+        self.save_error.write().unwrap().take()
+    }
+
+    ///The token profile the client is using, `"default"` unless one was picked with
+    ///`ClientBuilder::profile` or `switch_profile`
+    pub fn profile(&self) -> &str {
+        &self.profile
+    }
+
+    ///Switches to another user's tokens in the cache dir, loading them in place of the current
+    ///ones. Afterwards `need_auth` is true if that profile has never logged in. The client is
+    ///reused, so no new connections have to be made.
+    ///
+    ///This enables caching and replaces any store set with `set_token_store`.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{ClientBuilder, MALError};
+    /// # use std::path::PathBuf;
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::from("/some/dir")).profile("alice".to_string()).build_with_refresh().await?;
+    ///     client.switch_profile("bob").await?;
+    ///     if client.need_auth {
+    ///         //log bob in
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn switch_profile(&mut self, name: &str) -> Result<(), MALError> {
+        //checks the name before anything changes
+        store::profile_dir(&self.dirs, name)?;
+        let previous = std::mem::replace(&mut self.profile, name.to_owned());
+        let store = self.file_store();
+        let cached = match store.load().await {
+            Ok(cached) => cached,
+            Err(e) => {
+                self.profile = previous;
+                return Err(e);
             }
-            let res_raw = i.url();
-            debug!("raw response: {}", res_raw);
-            code = res_raw
-                .split_once('=')
-                .unwrap()
-                .1
-                .split_once('&')
+        };
+        match cached {
+            Some(tokens) => {
+                *self.expires_at.get_mut().unwrap() = Some(tokens.expires_at());
+                *self.access_token.get_mut().unwrap() = tokens.access_token;
+                *self.refresh_token.get_mut().unwrap() = tokens.refresh_token;
+                self.need_auth = false;
+            }
+            None => {
+                self.access_token.get_mut().unwrap().clear();
+                self.refresh_token.get_mut().unwrap().clear();
+                *self.expires_at.get_mut().unwrap() = None;
+                self.need_auth = true;
+            }
+        }
+        self.store = Some(store);
+        Ok(())
+    }
+
+    ///Lists the profiles with tokens saved in the cache dir, sorted by name
+    pub async fn list_profiles(&self) -> Result<Vec<String>, MALError> {
+        store::list_profiles(&self.dirs).await
+    }
+
+    ///Deletes a profile's tokens from the cache dir. If it's the profile the client is using this
+    ///also logs out.
+    pub async fn delete_profile(&mut self, name: &str) -> Result<(), MALError> {
+        FileTokenStore::new(self.dirs.clone())
+            .profile(name)
+            .clear()
+            .await?;
+        if name == self.profile {
+            self.logout().await?;
+        }
+        Ok(())
+    }
+
+    ///Stores new tokens on the client and saves them to the token store if caching is enabled.
+    ///A failed save is kept for `take_save_error` instead of being returned.
+    async fn save_tokens(&self, tokens: TokenResponse) {
+        *self.access_token.write().unwrap() = tokens.access_token.clone();
+        *self.refresh_token.write().unwrap() = tokens.refresh_token.clone();
+        *self.expires_at.write().unwrap() =
+            Some(SystemTime::now() + Duration::from_secs(tokens.expires_in as u64));
+
+        let tjson = Tokens {
+            access_token: tokens.access_token,
+            refresh_token: tokens.refresh_token,
+            expires_in: tokens.expires_in,
+            today: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
-                .0
-                .to_owned();
-            let response = Response::from_string("You're logged in! You can now close this window");
-            i.respond(response).unwrap();
-            break;
+                .as_secs(),
+        };
+        if let Some(hook) = &self.on_token_refresh {
+            hook(&tjson);
+        }
+        //the new tokens already work, so failing to keep them shouldn't fail the login or refresh
+        if let Some(store) = &self.store {
+            let saved = store.save(&tjson).await;
+            if let Err(e) = &saved {
+                warn!("Couldn't save the new tokens: {}", e);
+            }
+            *self.save_error.write().unwrap() = saved.err();
         }
+    }
 
-        self.get_tokens(&code, challenge).await
+    ///Returns true if the access token can be refreshed and expires within the refresh margin
+    fn expires_soon(&self) -> bool {
+        let can_refresh = !self.refresh_token.read().unwrap().is_empty();
+        match *self.expires_at.read().unwrap() {
+            Some(expires) => can_refresh && expires <= SystemTime::now() + self.refresh_margin,
+            None => false,
+        }
     }
 
-    async fn get_tokens(&mut self, code: &str, verifier: &str) -> Result<(), MALError> {
-        let params = [
-            ("client_id", self.client_secret.as_str()),
-            ("grant_type", "authorization_code"),
-            ("code_verifier", verifier),
-            ("code", code),
-        ];
-        let rec = self
-            .client
-            .request(Method::POST, "https://myanimelist.net/v1/oauth2/token")
-            .form(&params)
-            .build()
-            .unwrap();
-        let res = self.client.execute(rec).await.unwrap();
-        let text = res.text().await.unwrap();
-        if let Ok(tokens) = serde_json::from_str::<TokenResponse>(&text) {
-            self.access_token = tokens.access_token.clone();
-
-            let tjson = Tokens {
-                access_token: tokens.access_token,
-                refresh_token: tokens.refresh_token,
-                expires_in: tokens.expires_in,
-                today: SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs(),
+    ///Refreshes the access token unless another request already replaced `used_token` while
+    ///this one was waiting, so concurrent requests only trigger a single refresh
+    async fn refresh_guarded(&self, used_token: &str) -> Result<(), MALError> {
+        let _guard = self.refresh_lock.lock().await;
+        if **self.access_token.read().unwrap() == used_token {
+            self.refresh().await?;
+        }
+        Ok(())
+    }
+
+    ///A copy of the access token that's wiped when dropped, unlike `get_access_token`
+    fn current_access_token(&self) -> Zeroizing<String> {
+        self.access_token.read().unwrap().clone()
+    }
+
+    ///Sends a request with the access token as the auth header. The token is refreshed first if
+    ///it's about to expire, and if MAL rejects it anyway it's refreshed and the request is retried
+    ///once before giving up with an `unauthorized` error
+    async fn send_authed(&self, req: RequestBuilder) -> Result<reqwest::Response, MALError> {
+        if self.expires_soon() {
+            debug!("access token expires soon, refreshing");
+            self.refresh_guarded(&self.current_access_token()).await?;
+        }
+        let retry = req.try_clone();
+        let used_token = self.current_access_token();
+        let mut res = req.bearer_auth(used_token.as_str()).send().await?;
+        let can_refresh = !self.refresh_token.read().unwrap().is_empty();
+        if let (StatusCode::UNAUTHORIZED, Some(retry), true) = (res.status(), retry, can_refresh) {
+            debug!("access token rejected, refreshing and retrying");
+            self.refresh_guarded(&used_token).await?;
+            res = retry
+                .bearer_auth(self.current_access_token().as_str())
+                .send()
+                .await?;
+        }
+        if res.status() == StatusCode::UNAUTHORIZED {
+            let body = res.text().await.unwrap_or_default();
+            let message = match ApiError::parse(&body) {
+                Some(ApiError {
+                    message: Some(message),
+                    ..
+                }) => message,
+                Some(e) => format!("Access token was rejected: {}", e.error),
+                None => "Access token was rejected".to_owned(),
             };
-            if self.caching {
-                let mut f =
-                    File::create(self.dirs.join("tokens")).expect("Unable to create token file");
-                f.write_all(&encrypt_token(tjson))
-                    .expect("Unable to write tokens");
-            }
-            Ok(())
-        } else {
-            Err(MALError::new("Unable to get tokens", "None", text))
+            return Err(MALError::Auth {
+                error: "unauthorized".to_owned(),
+                message,
+            });
         }
+        Ok(res)
     }
 
     ///Sends a get request to the specified URL with the appropriate auth header
-    async fn do_request(&self, url: String) -> Result<String, MALError> {
-        match self
-            .client
-            .get(url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await
-        {
-            Ok(res) => Ok(res.text().await.unwrap()),
-            Err(e) => Err(MALError::new(
-                "Unable to send request",
-                &format!("{}", e),
-                None,
-            )),
-        }
+    async fn do_request(&self, url: String) -> Result<ApiResponse, MALError> {
+        let res = self.send_authed(self.client.get(url)).await?;
+        ApiResponse::read(res).await
     }
 
-    ///Sends a put request to the specified URL with the appropriate auth header and
+    ///Sends a patch request to the specified URL with the appropriate auth header and
     ///form encoded parameters
-    async fn do_request_forms(
+    async fn do_request_patch(
         &self,
         url: String,
         params: Vec<(&str, String)>,
-    ) -> Result<String, MALError> {
-        match self
-            .client
-            .put(url)
-            .bearer_auth(&self.access_token)
-            .form(&params)
-            .send()
-            .await
-        {
-            Ok(res) => Ok(res.text().await.unwrap()),
-            Err(e) => Err(MALError::new(
-                "Unable to send request",
-                &format!("{}", e),
-                None,
-            )),
-        }
+    ) -> Result<ApiResponse, MALError> {
+        let res = self
+            .send_authed(self.client.patch(url).form(&params))
+            .await?;
+        ApiResponse::read(res).await
     }
 
-    ///Tries to parse a JSON response string into the type provided in the `::<>` turbofish
-    fn parse_response<'a, T: Serialize + Deserialize<'a>>(
-        &self,
-        res: &'a str,
-    ) -> Result<T, MALError> {
-        match serde_json::from_str::<T>(res) {
+    ///Tries to parse a JSON response into the type provided in the `::<>` turbofish
+    fn parse_response<T: DeserializeOwned>(&self, res: &ApiResponse) -> Result<T, MALError> {
+        match serde_json::from_str::<T>(&res.body) {
             Ok(v) => Ok(v),
-            Err(_) => Err(match serde_json::from_str::<MALError>(res) {
-                Ok(o) => o,
-                Err(e) => MALError::new(
-                    "unable to parse response",
-                    &format!("{}", e),
-                    res.to_string(),
-                ),
-            }),
+            //MAL occasionally sends an error with a successful status
+            Err(_) if ApiError::parse(&res.body).is_some() => Err(MALError::from_response(
+                res.status.as_u16(),
+                res.body.clone(),
+            )),
+            Err(e) => Err(MALError::parse(e, &res.body)),
         }
     }
 
-    ///Returns the current access token. Intended mostly for debugging.
+    ///Returns a copy of the current access token. Intended mostly for debugging.
+    ///
+    ///Unlike the client's own copy this isn't wiped from memory when it's dropped.
     ///
     ///# Example
     ///
@@ -289,13 +1006,39 @@ impl MALClient {
     /// # use lib_mal::MALError;
     /// # use std::path::PathBuf;
     /// # async fn test() -> Result<(), MALError> {
-    ///     let client = ClientBuilder::new().secret("[YOUR_SECRET_HERE]".to_string()).caching(true).cache_dir(Some(PathBuf::new())).build_with_refresh().await?;
+    ///     let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(Some(PathBuf::new())).build_with_refresh().await?;
     ///     let token = client.get_access_token();
     ///     Ok(())
     /// # }
     ///```
-    pub fn get_access_token(&self) -> &str {
-        &self.access_token
+    pub fn get_access_token(&self) -> String {
+        self.access_token.read().unwrap().to_string()
+    }
+
+    ///Returns when the current access token expires, if it's known. Clients created with
+    ///`with_access_token` don't know when their token expires.
+    pub fn expires_at(&self) -> Option<SystemTime> {
+        *self.expires_at.read().unwrap()
+    }
+
+    ///Returns true if the access token is known to have expired
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{ClientBuilder, MALError};
+    /// # use std::path::PathBuf;
+    /// # async fn test() -> Result<(), MALError> {
+    ///     let client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::new()).build_with_refresh().await?;
+    ///     if client.need_auth || client.is_token_expired() {
+    ///         //show the login screen
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub fn is_token_expired(&self) -> bool {
+        self.expires_at()
+            .is_some_and(|expires| expires <= SystemTime::now())
     }
 
     //Begin API functions
@@ -304,14 +1047,20 @@ impl MALClient {
     ///Gets a list of anime based on the query string provided
     ///`limit` defaults to 100 if `None`
     ///
+    ///MAL only sends the `id`, `title` and `main_picture` of each anime unless more `fields` are
+    ///asked for
+    ///
     ///# Example
     ///
     ///```no_run
     /// # use lib_mal::MALClient;
     /// # use lib_mal::MALError;
+    /// use lib_mal::model::fields::AnimeFields;
     /// # async fn test() -> Result<(), MALError> {
     ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
-    ///     let list = client.get_anime_list("Mobile Suit Gundam", None).await?;
+    ///     let list = client.get_anime_list("Mobile Suit Gundam", None, None).await?;
+    ///     let fields = AnimeFields::Mean | AnimeFields::NumEpisodes | AnimeFields::MediaType;
+    ///     let with_scores = client.get_anime_list("Mobile Suit Gundam", None, fields).await?;
     ///     # Ok(())
     /// # }
     ///```
@@ -319,12 +1068,15 @@ impl MALClient {
         &self,
         query: &str,
         limit: impl Into<Option<u8>>,
+        fields: impl Into<Option<AnimeFields>>,
     ) -> Result<AnimeList, MALError> {
-        let url = format!(
-            "https://api.myanimelist.net/v2/anime?q={}&limit={}",
+        let mut url = format!(
+            "{}/anime?q={}&limit={}",
+            self.api_url,
             query,
             limit.into().unwrap_or(100)
         );
+        push_fields(&mut url, fields.into());
         let res = self.do_request(url).await?;
         self.parse_response(&res)
     }
@@ -350,35 +1102,36 @@ impl MALClient {
     ///
     pub async fn get_anime_details(
         &self,
-        id: u32,
+        id: impl Into<AnimeId>,
         fields: impl Into<Option<AnimeFields>>,
     ) -> Result<AnimeDetails, MALError> {
+        let id = id.into();
         let url = if let Some(f) = fields.into() {
-            format!("https://api.myanimelist.net/v2/anime/{}?fields={}", id, f)
+            format!("{}/anime/{}?fields={}", self.api_url, id, f)
         } else {
-            format!(
-                "https://api.myanimelist.net/v2/anime/{}?fields={}",
-                id,
-                AnimeFields::ALL
-            )
+            format!("{}/anime/{}?fields={}", self.api_url, id, AnimeFields::ALL)
         };
-        let res = self.do_request(url).await?;
+        let res = self.do_request(url).await.map_err(not_found("anime", id))?;
         self.parse_response(&res)
     }
 
     ///Gets a list of anime ranked by `RankingType`
     ///
-    ///`limit` defaults to the max of 100 when `None`
+    ///`limit` defaults to the max of 100 when `None`. Each entry has its `ranking`, and the anime
+    ///only has the `id`, `title` and `main_picture` unless more `fields` are asked for.
     ///
     ///# Example
     ///
     ///```no_run
     /// # use lib_mal::{MALError, MALClient};
-    /// use lib_mal::model::options::RankingType;
+    /// use lib_mal::model::{fields::AnimeFields, options::RankingType};
     /// # async fn test() -> Result<(), MALError> {
     ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
     /// // Gets a list of the top 5 most popular anime
-    /// let ranking_list = client.get_anime_ranking(RankingType::ByPopularity, 5).await?;
+    /// let ranking_list = client.get_anime_ranking(RankingType::ByPopularity, 5, None).await?;
+    /// // and the top 50 with their scores
+    /// let fields = AnimeFields::Mean | AnimeFields::NumListUsers | AnimeFields::MediaType;
+    /// let top_anime = client.get_anime_ranking(RankingType::All, 50, fields).await?;
     /// # Ok(())
     /// # }
     ///
@@ -387,28 +1140,34 @@ impl MALClient {
         &self,
         ranking_type: RankingType,
         limit: impl Into<Option<u8>>,
+        fields: impl Into<Option<AnimeFields>>,
     ) -> Result<AnimeList, MALError> {
-        let url = format!(
-            "https://api.myanimelist.net/v2/anime/ranking?ranking_type={}&limit={}",
+        let mut url = format!(
+            "{}/anime/ranking?ranking_type={}&limit={}",
+            self.api_url,
             ranking_type,
             limit.into().unwrap_or(100)
         );
+        push_fields(&mut url, fields.into());
         let res = self.do_request(url).await?;
-        Ok(serde_json::from_str(&res).unwrap())
+        self.parse_response(&res)
     }
 
     ///Gets the anime for a given season in a given year
     ///
-    ///`limit` defaults to the max of 100 when `None`
+    ///`limit` defaults to the max of 100 when `None`. Each anime only has the `id`, `title` and
+    ///`main_picture` unless more `fields` are asked for.
     ///
     ///# Example
     ///
     ///```no_run
     /// # use lib_mal::{MALClient, MALError};
-    /// use lib_mal::model::options::Season;
+    /// use lib_mal::model::{fields::AnimeFields, options::Season};
     /// # async fn test() -> Result<(), MALError> {
     ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
-    ///     let summer_2019 = client.get_seasonal_anime(Season::Summer, 2019, None).await?;
+    ///     let summer_2019 = client.get_seasonal_anime(Season::Summer, 2019, None, None).await?;
+    ///     let fields = AnimeFields::Mean | AnimeFields::Genres | AnimeFields::Broadcast;
+    ///     let chart = client.get_seasonal_anime(Season::Summer, 2019, None, fields).await?;
     ///     # Ok(())
     /// # }
     ///```
@@ -417,13 +1176,16 @@ impl MALClient {
         season: Season,
         year: u32,
         limit: impl Into<Option<u8>>,
+        fields: impl Into<Option<AnimeFields>>,
     ) -> Result<AnimeList, MALError> {
-        let url = format!(
-            "https://api.myanimelist.net/v2/anime/season/{}/{}?limit={}",
+        let mut url = format!(
+            "{}/anime/season/{}/{}?limit={}",
+            self.api_url,
             year,
             season,
             limit.into().unwrap_or(100)
         );
+        push_fields(&mut url, fields.into());
         let res = self.do_request(url).await?;
         self.parse_response(&res)
     }
@@ -431,44 +1193,85 @@ impl MALClient {
     ///Returns the suggested anime for the current user. Can return an empty list if the user has
     ///no suggestions.
     ///
+    ///Each anime only has the `id`, `title` and `main_picture` unless more `fields` are asked
+    ///for
+    ///
     ///# Example
     ///
     ///```no_run
     /// # use lib_mal::{MALClient, MALError};
+    /// use lib_mal::model::fields::AnimeFields;
     /// # async fn test() -> Result<(), MALError> {
     ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
-    ///     let suggestions = client.get_suggested_anime(10).await?;
+    ///     let suggestions = client.get_suggested_anime(10, None).await?;
+    ///     let cards = client
+    ///         .get_suggested_anime(10, AnimeFields::Synopsis | AnimeFields::Genres)
+    ///         .await?;
     ///     # Ok(())
     /// # }
     ///```
     pub async fn get_suggested_anime(
         &self,
         limit: impl Into<Option<u8>>,
+        fields: impl Into<Option<AnimeFields>>,
     ) -> Result<AnimeList, MALError> {
-        let url = format!(
-            "https://api.myanimelist.net/v2/anime/suggestions?limit={}",
+        let mut url = format!(
+            "{}/anime/suggestions?limit={}",
+            self.api_url,
             limit.into().unwrap_or(100)
         );
+        push_fields(&mut url, fields.into());
         let res = self.do_request(url).await?;
         self.parse_response(&res)
     }
 
     //--User anime list functions--//
 
+    ///Gets the user's list status for a single anime, or `None` if the anime isn't on their list
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     if let Some(status) = client.get_my_anime_list_status(80).await? {
+    ///         println!("Watched {} episodes", status.num_episodes_watched.unwrap_or(0));
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn get_my_anime_list_status(
+        &self,
+        id: impl Into<AnimeId>,
+    ) -> Result<Option<ListStatus>, MALError> {
+        let id = id.into();
+        let url = format!(
+            "{}/anime/{}?fields={}",
+            self.api_url,
+            id,
+            AnimeFields::MyListStatus
+        );
+        let res = self.do_request(url).await.map_err(not_found("anime", id))?;
+        let details: AnimeDetails = self.parse_response(&res)?;
+        Ok(details.my_list_status)
+    }
+
     ///Adds an anime to the list, or updates the element if it already exists
     ///
+    ///The update is validated before it's sent, see `StatusUpdate::validate`
+    ///
     ///# Example
     ///
     ///```no_run
     /// # use lib_mal::{MALClient, MALError};
-    /// use lib_mal::model::StatusBuilder;
-    /// use lib_mal::model::options::Status;
+    /// use lib_mal::model::options::{StatusUpdate, WatchStatus};
     /// # async fn test() -> Result<(), MALError> {
     ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
     ///     // add a new anime to the user's list
-    ///     let updated_status = client.update_user_anime_status(80, StatusBuilder::new().status(Status::Watching).build()).await?;
+    ///     let updated_status = client.update_user_anime_status(80, StatusUpdate::new().status(WatchStatus::Watching)).await?;
     ///     // or update an existing one
-    ///     let new_status = StatusBuilder::new().status(Status::Dropped).num_watched_episodes(2).build();
+    ///     let new_status = StatusUpdate::new().status(WatchStatus::Dropped).num_watched_episodes(2);
     ///     let updated_status = client.update_user_anime_status(32981, new_status).await?;
     ///
     ///     # Ok(())
@@ -477,15 +1280,180 @@ impl MALClient {
     ///```
     pub async fn update_user_anime_status(
         &self,
-        id: u32,
+        id: impl Into<AnimeId>,
         update: StatusUpdate,
     ) -> Result<ListStatus, MALError> {
+        let id = id.into();
+        update.validate()?;
         let params = update.get_params();
-        let url = format!("https://api.myanimelist.net/v2/anime/{}/my_list_status", id);
-        let res = self.do_request_forms(url, params).await?;
+        let url = format!("{}/anime/{}/my_list_status", self.api_url, id);
+        let res = self
+            .do_request_patch(url, params)
+            .await
+            .map_err(not_found("anime", id))?;
         self.parse_response(&res)
     }
 
+    ///Applies many list updates at once, with at most `concurrency` requests in flight. Updates
+    ///that get rate limited are retried with an increasing delay.
+    ///
+    ///Returns the result for each anime id in the same order as `updates`
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// use lib_mal::model::{options::{StatusUpdate, WatchStatus}, AnimeId};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     let updates = vec![
+    ///         (80, StatusUpdate::new().status(WatchStatus::Completed)),
+    ///         (32981, StatusUpdate::new().num_watched_episodes(4)),
+    ///     ];
+    ///     let results = client.update_user_anime_statuses(updates, 4).await;
+    ///     let failed: Vec<AnimeId> = results.iter().filter(|(_, r)| r.is_err()).map(|(id, _)| *id).collect();
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn update_user_anime_statuses(
+        &self,
+        updates: Vec<(impl Into<AnimeId>, StatusUpdate)>,
+        concurrency: usize,
+    ) -> Vec<(AnimeId, Result<ListStatus, MALError>)> {
+        stream::iter(updates)
+            .map(|(id, update)| async move {
+                let id = id.into();
+                (id, self.update_with_backoff(id, update).await)
+            })
+            .buffered(concurrency.max(1))
+            .collect()
+            .await
+    }
+
+    ///`update_user_anime_status`, retried with an increasing delay while MAL rate limits it
+    async fn update_with_backoff(
+        &self,
+        id: AnimeId,
+        update: StatusUpdate,
+    ) -> Result<ListStatus, MALError> {
+        let mut delay = BACKOFF_START;
+        let mut tries = 0;
+        loop {
+            match self.update_user_anime_status(id, update.clone()).await {
+                Err(MALError::RateLimited { retry_after }) if tries < BACKOFF_TRIES => {
+                    //MAL knows best how long to wait
+                    let wait = retry_after.unwrap_or(delay);
+                    debug!("rate limited updating {}, retrying in {:?}", id, wait);
+                    tokio::time::sleep(wait).await;
+                    delay *= 2;
+                    tries += 1;
+                }
+                res => return res,
+            }
+        }
+    }
+
+    ///Adds one to the number of watched episodes for the anime with `id`. If the anime isn't on
+    ///the user's list yet it's added as watching with one episode watched.
+    ///
+    ///When `complete` is true the status is also set to completed once the last episode has been
+    ///watched
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     let new_status = client.increment_watched_episodes(80, true).await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn increment_watched_episodes(
+        &self,
+        id: impl Into<AnimeId>,
+        complete: bool,
+    ) -> Result<ListStatus, MALError> {
+        let id = id.into();
+        let url = format!(
+            "{}/anime/{}?fields={}",
+            self.api_url,
+            id,
+            AnimeFields::MyListStatus | AnimeFields::NumEpisodes
+        );
+        let res = self.do_request(url).await.map_err(not_found("anime", id))?;
+        let details: AnimeDetails = self.parse_response(&res)?;
+
+        let watched = details
+            .my_list_status
+            .map(|s| s.num_episodes_watched.unwrap_or(0) + 1);
+        let mut update = StatusUpdate::new().num_watched_episodes(watched.unwrap_or(1));
+        if watched.is_none() {
+            update = update.status(WatchStatus::Watching);
+        }
+        //there's no total while MAL doesn't know it yet
+        if let Some(total) = details.num_episodes {
+            if complete && watched.unwrap_or(1) >= total {
+                update = update.status(WatchStatus::Completed);
+            }
+        }
+
+        self.update_user_anime_status(id, update).await
+    }
+
+    ///Sets the status of the anime with `id` to completed and the number of watched episodes to
+    ///the anime's episode count
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     let new_status = client.mark_completed(80).await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn mark_completed(&self, id: impl Into<AnimeId>) -> Result<ListStatus, MALError> {
+        let id = id.into();
+        let details = self.get_anime_details(id, AnimeFields::NumEpisodes).await?;
+        let update = StatusUpdate::new()
+            .status(WatchStatus::Completed)
+            .num_watched_episodes(details.num_episodes);
+
+        self.update_user_anime_status(id, update).await
+    }
+
+    ///Sets the user's score for the anime with `id` without changing anything else.
+    ///`score` has to be between 1 and 10
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     let new_status = client.set_score(80, 9).await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn set_score(
+        &self,
+        id: impl Into<AnimeId>,
+        score: u8,
+    ) -> Result<ListStatus, MALError> {
+        if !(1..=10).contains(&score) {
+            return Err(MALError::new(
+                "Score must be between 1 and 10",
+                "invalid_score",
+                score.to_string(),
+            ));
+        }
+
+        self.update_user_anime_status(id, StatusUpdate::new().score(score))
+            .await
+    }
+
     ///Returns the user's full anime list as an `AnimeList` struct.
     ///
     ///# Example
@@ -500,18 +1468,66 @@ impl MALClient {
     /// # }
     ///```
     pub async fn get_user_anime_list(&self) -> Result<AnimeList, MALError> {
-        let url = "https://api.myanimelist.net/v2/users/@me/animelist?fields=list_status&limit=4";
-        let res = self.do_request(url.to_owned()).await?;
+        let url = format!(
+            "{}/users/@me/animelist?fields=list_status&limit=4",
+            self.api_url
+        );
+        let res = self.do_request(url).await?;
 
         self.parse_response(&res)
     }
 
+    ///Returns the entries in the user's anime list that have been updated since `since`, most
+    ///recently updated first. Pages are only requested until an older entry is found, so this is
+    ///a lot cheaper than getting the whole list when only a few entries have changed.
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// use chrono::{Duration, Utc};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     let changed = client.get_user_anime_list_updated_since(Utc::now() - Duration::days(1)).await?;
+    ///     # Ok(())
+    /// # }
+    ///```
+    #[cfg(feature = "chrono")]
+    pub async fn get_user_anime_list_updated_since(
+        &self,
+        since: chrono::DateTime<chrono::Utc>,
+    ) -> Result<Vec<ListNode>, MALError> {
+        let mut url = format!(
+            "{}/users/@me/animelist?fields=list_status&sort=list_updated_at&limit=100",
+            self.api_url
+        );
+        let mut changed = vec![];
+        loop {
+            let res = self.do_request(url).await?;
+            let page: AnimeList = self.parse_response(&res)?;
+            let next = page.next_page().map(str::to_owned);
+            for node in page.data {
+                match node.list_status.as_ref().and_then(|s| s.updated_at) {
+                    Some(updated) if updated >= since => changed.push(node),
+                    _ => return Ok(changed),
+                }
+            }
+            match next {
+                Some(n) => url = n,
+                None => return Ok(changed),
+            }
+        }
+    }
+
     ///Deletes the anime with `id` from the user's anime list
     ///
+    ///Anime that aren't on the list are a `NotFound` error, see `try_delete_anime_list_item` to
+    ///check for that without matching on the error. Any other unsuccessful response is an error as
+    ///well.
+    ///
     ///# Note
     /// The [API docs from MAL](https://myanimelist.net/apiconfig/references/api/v2#operation/anime_anime_id_my_list_status_delete) say this method should return 404 if the anime isn't in the user's
-    /// list, but in my testing this wasn't true. Without that there's no way to tell if the item
-    /// was actually deleted or not.
+    /// list, but in my testing this wasn't always true.
     ///
     ///# Example
     ///
@@ -523,40 +1539,48 @@ impl MALClient {
     ///     # Ok(())
     /// # }
     ///```
-    pub async fn delete_anime_list_item(&self, id: u32) -> Result<(), MALError> {
-        let url = format!("https://api.myanimelist.net/v2/anime/{}/my_list_status", id);
-        let res = self
-            .client
-            .delete(url)
-            .bearer_auth(&self.access_token)
-            .send()
-            .await;
-        match res {
-            Ok(r) => {
-                if r.status() == StatusCode::NOT_FOUND {
-                    Err(MALError::new(
-                        &format!("Anime {} not found", id),
-                        r.status().as_str(),
-                        None,
-                    ))
-                } else {
-                    Ok(())
-                }
-            }
-            Err(e) => Err(MALError::new(
-                "Unable to send request",
-                &format!("{}", e),
-                None,
-            )),
+    pub async fn delete_anime_list_item(&self, id: impl Into<AnimeId>) -> Result<(), MALError> {
+        let id = id.into();
+        let url = format!("{}/anime/{}/my_list_status", self.api_url, id);
+        let res = self.send_authed(self.client.delete(url)).await?;
+        ApiResponse::read(res)
+            .await
+            .map_err(not_found("anime", id))?;
+        Ok(())
+    }
+
+    ///Deletes the anime with `id` from the user's anime list like `delete_anime_list_item`, but
+    ///returns whether it was on the list instead of a `NotFound` error
+    ///
+    ///# Example
+    ///
+    ///```no_run
+    /// # use lib_mal::{MALClient, MALError};
+    /// # async fn test() -> Result<(), MALError> {
+    ///     # let client = MALClient::with_access_token("[YOUR_SECRET_HERE]");
+    ///     if !client.try_delete_anime_list_item(80).await? {
+    ///         println!("Anime 80 wasn't on the list");
+    ///     }
+    ///     # Ok(())
+    /// # }
+    ///```
+    pub async fn try_delete_anime_list_item(
+        &self,
+        id: impl Into<AnimeId>,
+    ) -> Result<bool, MALError> {
+        match self.delete_anime_list_item(id).await {
+            Ok(()) => Ok(true),
+            Err(MALError::NotFound { .. }) => Ok(false),
+            Err(e) => Err(e),
         }
     }
 
     //--Forum functions--//
 
-    ///Returns a vector of `HashMap`s that represent all the forum boards on MAL
+    ///Returns all the forum boards on MAL, grouped into categories
     pub async fn get_forum_boards(&self) -> Result<ForumBoards, MALError> {
         let res = self
-            .do_request("https://api.myanimelist.net/v2/forum/boards".to_owned())
+            .do_request(format!("{}/forum/boards", self.api_url))
             .await?;
         self.parse_response(&res)
     }
@@ -564,23 +1588,28 @@ impl MALClient {
     ///Returns details of the specified topic
     pub async fn get_forum_topic_detail(
         &self,
-        topic_id: u32,
+        topic_id: impl Into<TopicId>,
         limit: impl Into<Option<u8>>,
     ) -> Result<TopicDetails, MALError> {
+        let topic_id = topic_id.into();
         let url = format!(
-            "https://api.myanimelist.net/v2/forum/topic/{}?limit={}",
+            "{}/forum/topic/{}?limit={}",
+            self.api_url,
             topic_id,
             limit.into().unwrap_or(100)
         );
-        let res = self.do_request(url).await?;
+        let res = self
+            .do_request(url)
+            .await
+            .map_err(not_found("topic", topic_id))?;
         self.parse_response(&res)
     }
 
-    ///Returns all topics for a given query
+    ///Returns the forum topics matching the given filters
     pub async fn get_forum_topics(
         &self,
-        board_id: impl Into<Option<u32>>,
-        subboard_id: impl Into<Option<u32>>,
+        board_id: impl Into<Option<BoardId>>,
+        subboard_id: impl Into<Option<SubboardId>>,
         query: impl Into<Option<String>>,
         topic_user_name: impl Into<Option<String>>,
         user_name: impl Into<Option<String>>,
@@ -604,9 +1633,9 @@ impl MALClient {
                 tmp.push(format!("user_name={}", bid));
             }
             tmp.push(format!("limit={}", limit.into().unwrap_or(100)));
-            tmp.join(",")
+            tmp.join("&")
         };
-        let url = format!("https://api.myanimelist.net/v2/forum/topics?{}", params);
+        let url = format!("{}/forum/topics?{}", self.api_url, params);
         let res = self.do_request(url).await?;
         self.parse_response(&res)
     }
@@ -624,50 +1653,201 @@ impl MALClient {
     /// # }
     ///```
     pub async fn get_my_user_info(&self) -> Result<User, MALError> {
-        let url = "https://api.myanimelist.net/v2/users/@me?fields=anime_statistics";
-        let res = self.do_request(url.to_owned()).await?;
+        let url = format!(
+            "{}/users/@me?fields=anime_statistics,time_zone,is_supporter",
+            self.api_url
+        );
+        let res = self.do_request(url).await?;
         self.parse_response(&res)
     }
 }
 
+///Generates an unguessable `state` for the auth URL from 32 bytes of OS randomness
+fn random_state() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+///Reads the `Retry-After` header, which MAL sends as a number of seconds
+fn retry_after(headers: &reqwest::header::HeaderMap) -> Option<Duration> {
+    let secs = headers.get(reqwest::header::RETRY_AFTER)?.to_str().ok()?;
+    secs.trim().parse().ok().map(Duration::from_secs)
+}
+
+///Adds `fields` to the query of `url`, if there are any
+fn push_fields(url: &mut String, fields: Option<AnimeFields>) {
+    if let Some(fields) = fields.filter(|f| !f.is_empty()) {
+        url.push_str(&format!("&fields={}", fields));
+    }
+}
+
+///Turns the error for a 404 into a `NotFound` error for the anime or topic with `id`
+fn not_found(resource: &'static str, id: impl Into<u32>) -> impl FnOnce(MALError) -> MALError {
+    let id = id.into();
+    move |e| match e.status() {
+        Some(404) => MALError::NotFound { resource, id },
+        _ => e,
+    }
+}
+
+///Parses a token response. MAL sends back an error instead when the code or refresh token is
+///rejected.
+fn parse_token_response(text: &str) -> Result<TokenResponse, MALError> {
+    serde_json::from_str(text).map_err(|e| match ApiError::parse(text) {
+        Some(body) => MALError::Auth {
+            message: body.message.unwrap_or_default(),
+            error: body.error,
+        },
+        None => {
+            let mut err = MALError::parse(e, text);
+            if let MALError::Parse { snippet, .. } = &mut err {
+                *snippet = redact_tokens(text);
+            }
+            err
+        }
+    })
+}
+
+///Hides any tokens in a token response that couldn't be parsed, so it can go in an error
+fn redact_tokens(text: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(text) {
+        Ok(serde_json::Value::Object(mut body)) => {
+            for key in ["access_token", "refresh_token"] {
+                if let Some(token) = body.get_mut(key) {
+                    *token = REDACTED.into();
+                }
+            }
+            serde_json::Value::Object(body).to_string()
+        }
+        //there's no telling where a token in a broken body ends, so everything from the first
+        //one on is dropped
+        _ => match ["\"access_token\"", "\"refresh_token\""]
+            .iter()
+            .filter_map(|key| text.find(key))
+            .min()
+        {
+            Some(start) => format!("{}{}", &text[..start], REDACTED),
+            None => text.to_owned(),
+        },
+    }
+}
+
+///Leaves the tokens and client secret out, so a client can be logged safely
+impl Debug for MALClient {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("MALClient")
+            .field("client_id", &self.client_id)
+            .field(
+                "client_secret",
+                &self.client_secret.as_ref().map(|_| REDACTED),
+            )
+            .field("dirs", &self.dirs)
+            .field("profile", &self.profile)
+            .field("caching", &self.store.is_some())
+            .field("access_token", &REDACTED)
+            .field("refresh_token", &REDACTED)
+            .field("expires_at", &*self.expires_at.read().unwrap())
+            .field("need_auth", &self.need_auth)
+            .finish_non_exhaustive()
+    }
+}
+
+///Reads the body of a response, a failure is a `Read` error with where it came from
+async fn read_body(res: reqwest::Response) -> Result<String, MALError> {
+    let url = res.url().to_string();
+    let status = res.status().as_u16();
+    res.text().await.map_err(|source| MALError::Read {
+        url,
+        status,
+        source,
+    })
+}
+
+///A response from the API that's been read
+struct ApiResponse {
+    status: StatusCode,
+    body: String,
+}
+
+impl ApiResponse {
+    ///Reads the body of a response. Unsuccessful responses become a `RateLimited` error for 429,
+    ///an `Api` error if MAL sent one of its error messages, or an `Http` error otherwise.
+    async fn read(res: reqwest::Response) -> Result<Self, MALError> {
+        let status = res.status();
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(MALError::RateLimited {
+                retry_after: retry_after(res.headers()),
+            });
+        }
+        let body = read_body(res).await?;
+        if status.is_success() {
+            return Ok(ApiResponse { status, body });
+        }
+        Err(MALError::from_response(status.as_u16(), body))
+    }
+}
+
 #[derive(Deserialize)]
 pub(crate) struct TokenResponse {
+    #[serde(rename = "token_type")]
     pub _token_type: String,
     pub expires_in: u32,
-    pub access_token: String,
-    pub refresh_token: String,
+    pub access_token: Zeroizing<String>,
+    pub refresh_token: Zeroizing<String>,
 }
 
-#[derive(Serialize, Deserialize)]
-pub(crate) struct Tokens {
-    pub access_token: String,
-    pub refresh_token: String,
-    pub expires_in: u32,
-    pub today: u64,
+///The tokens a client uses to talk to the API. Store these wherever you like and hand them back
+///to `MALClient::from_tokens` later to skip logging in again.
+///
+///Serializes to the same JSON that goes inside the encrypted token cache. The tokens are wiped
+///from memory when dropped and left out of the `Debug` output.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tokens {
+    pub(crate) access_token: Zeroizing<String>,
+    pub(crate) refresh_token: Zeroizing<String>,
+    pub(crate) expires_in: u32,
+    pub(crate) today: u64,
 }
 
-pub(crate) fn encrypt_token(toks: Tokens) -> Vec<u8> {
-    let key = Key::from_slice(b"one two three four five six seve");
-    let cypher = Aes256Gcm::new(key);
-    let nonce = Nonce::from_slice(b"but the eart");
-    let plain = serde_json::to_vec(&toks).unwrap();
-    let res = cypher.encrypt(nonce, plain.as_ref()).unwrap();
-    res
+impl Tokens {
+    ///Creates a set of tokens that expires at `expires_at`
+    pub fn new(access_token: String, refresh_token: String, expires_at: SystemTime) -> Self {
+        let secs = |t: SystemTime| {
+            t.duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs()
+        };
+        let today = secs(SystemTime::now());
+        let expires_in = secs(expires_at).saturating_sub(today);
+        Tokens {
+            access_token: access_token.into(),
+            refresh_token: refresh_token.into(),
+            expires_in: expires_in.min(u32::MAX as u64) as u32,
+            today,
+        }
+    }
+
+    pub fn access_token(&self) -> &str {
+        &self.access_token
+    }
+
+    pub fn refresh_token(&self) -> &str {
+        &self.refresh_token
+    }
+
+    ///When the access token expires
+    pub fn expires_at(&self) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_secs(self.today + self.expires_in as u64)
+    }
 }
 
-pub(crate) fn decrypt_tokens(raw: &[u8]) -> Result<Tokens, MALError> {
-    let key = Key::from_slice(b"one two three four five six seve");
-    let cypher = Aes256Gcm::new(key);
-    let nonce = Nonce::from_slice(b"but the eart");
-    match cypher.decrypt(nonce, raw.as_ref()) {
-        Ok(plain) => {
-            let text = String::from_utf8(plain).unwrap();
-            Ok(serde_json::from_str(&text).expect("couldn't parse decrypted tokens"))
-        }
-        Err(e) => Err(MALError::new(
-            "Unable to decrypt encrypted tokens",
-            &format!("{}", e),
-            None,
-        )),
+impl Debug for Tokens {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Tokens")
+            .field("access_token", &REDACTED)
+            .field("refresh_token", &REDACTED)
+            .field("expires_at", &self.expires_at())
+            .finish()
     }
 }
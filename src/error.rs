@@ -0,0 +1,319 @@
+use serde::Deserialize;
+use std::time::Duration;
+use thiserror::Error;
+
+///The errors returned by lib_mal. Match on the variant to tell failures apart, for example an
+///`Auth` error means the user has to log in again while an `Http` error with a 5xx status means
+///MAL is having trouble and the request can be retried later.
+///
+///Every error also has a short code from `error`, like `access_denied` or `invalid_score`. Errors
+///from reqwest, serde_json and the file system are kept as the `source` rather than repeated in
+///the message, and `MALError` is `Send + Sync + 'static` so it can be boxed or turned into an
+///`anyhow::Error`.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum MALError {
+    ///MAL answered with an unsuccessful status and a body that isn't one of its error messages
+    #[error("MAL responded with status {status}")]
+    Http { status: u16, body: String },
+    ///The anime, topic or list entry with `id` doesn't exist. `resource` is `"anime"` or
+    ///`"topic"`.
+    #[error("No {resource} with id {id}")]
+    NotFound { resource: &'static str, id: u32 },
+    ///MAL is rate limiting the client. `retry_after` is how long MAL asked to wait before trying
+    ///again, if it said.
+    #[error("Rate limited by MAL")]
+    RateLimited { retry_after: Option<Duration> },
+    ///MAL rejected the request with an error code, like `invalid_parameters`, and description.
+    ///`body` is the response as it was sent.
+    #[error("MAL returned an error: {error}")]
+    Api {
+        error: String,
+        message: Option<String>,
+        status: Option<u16>,
+        body: String,
+    },
+    ///A response couldn't be parsed. `path` is where in the body parsing failed, like
+    ///`data[3].node.start_season.year`, and `snippet` is the part of the body around it.
+    #[error("Unable to parse response{}", path_prefix(.path))]
+    Parse {
+        source: serde_json::Error,
+        path: String,
+        snippet: String,
+    },
+    ///Logging in or refreshing the tokens failed, or the access token was rejected. The user has
+    ///to log in again unless the error is from the callback listener, like `bind_failed`.
+    #[error("Authentication failed: {error}")]
+    Auth { error: String, message: String },
+    ///A request couldn't be sent or its response couldn't be read
+    #[error("Unable to send request")]
+    Request(#[source] reqwest::Error),
+    ///The body of a response from `url` couldn't be read, for example because the connection was
+    ///reset part way through
+    #[error("Unable to read the response from {url} (status {status})")]
+    Read {
+        url: String,
+        status: u16,
+        source: reqwest::Error,
+    },
+    ///Reading or writing the token cache failed
+    #[error("{message}")]
+    Io {
+        message: String,
+        source: std::io::Error,
+    },
+    ///Anything else, like invalid arguments or an unreadable token cache
+    #[error("lib_mal encountered an error: {error}")]
+    Other {
+        error: String,
+        message: Option<String>,
+        info: Option<String>,
+    },
+}
+
+impl MALError {
+    ///Creates an `Other` error
+    pub fn new(msg: &str, error: &str, info: impl Into<Option<String>>) -> Self {
+        MALError::Other {
+            error: error.to_owned(),
+            message: Some(msg.to_owned()),
+            info: info.into(),
+        }
+    }
+
+    ///A short code for the error, like `access_denied`. `Http`, `NotFound`, `RateLimited`,
+    ///`Parse`, `Request`, `Read` and `Io` errors are `http_error`, `not_found`, `rate_limited`,
+    ///`parse_error`, `request_failed`, `read_failed` and `io_error`.
+    pub fn error(&self) -> &str {
+        match self {
+            MALError::Http { .. } => "http_error",
+            MALError::NotFound { .. } => "not_found",
+            MALError::RateLimited { .. } => "rate_limited",
+            MALError::Api { error, .. } => error,
+            MALError::Parse { .. } => "parse_error",
+            MALError::Auth { error, .. } => error,
+            MALError::Request(_) => "request_failed",
+            MALError::Read { .. } => "read_failed",
+            MALError::Io { .. } => "io_error",
+            MALError::Other { error, .. } => error,
+        }
+    }
+
+    ///The HTTP status MAL responded with, if the error came from a response
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            MALError::Http { status, .. } => Some(*status),
+            MALError::NotFound { .. } => Some(404),
+            MALError::RateLimited { .. } => Some(429),
+            MALError::Api { status, .. } => *status,
+            MALError::Request(e) => e.status().map(|status| status.as_u16()),
+            MALError::Read { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    ///A description of the error, if there is one
+    pub fn message(&self) -> Option<&str> {
+        match self {
+            MALError::Api { message, .. } | MALError::Other { message, .. } => message.as_deref(),
+            MALError::Auth { message, .. } | MALError::Io { message, .. } => Some(message),
+            _ => None,
+        }
+    }
+
+    ///Extra details, like the body of a response that couldn't be parsed
+    pub fn info(&self) -> Option<&str> {
+        match self {
+            MALError::Http { body, .. } | MALError::Api { body, .. } => Some(body),
+            MALError::Parse { snippet, .. } => Some(snippet),
+            MALError::Other { info, .. } => info.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn auth(error: &str, message: &str) -> Self {
+        MALError::Auth {
+            error: error.to_owned(),
+            message: message.to_owned(),
+        }
+    }
+
+    pub(crate) fn io(message: &str, source: std::io::Error) -> Self {
+        MALError::Io {
+            message: message.to_owned(),
+            source,
+        }
+    }
+
+    ///A `Parse` error for `body`, with the path to where parsing stopped and the part of the body
+    ///around it
+    pub(crate) fn parse(source: serde_json::Error, body: &str) -> Self {
+        let offset = error_offset(&source, body);
+        MALError::Parse {
+            path: json_path(body, offset),
+            snippet: snippet(body, offset),
+            source,
+        }
+    }
+
+    ///The error for an unsuccessful response, an `Api` error if the body is one of MAL's error
+    ///messages and an `Http` error if it isn't
+    pub(crate) fn from_response(status: u16, body: String) -> Self {
+        match ApiError::parse(&body) {
+            Some(e) => MALError::Api {
+                error: e.error,
+                message: e.message,
+                status: Some(status),
+                body,
+            },
+            None => MALError::Http { status, body },
+        }
+    }
+}
+
+///How much of the body is kept on each side of where parsing failed
+const SNIPPET_CONTEXT: usize = 250;
+
+///Where parsing failed for the `Parse` message, nothing when the path is empty
+fn path_prefix(path: &str) -> String {
+    if path.is_empty() {
+        String::new()
+    } else {
+        format!(": {}", path)
+    }
+}
+
+///The byte offset in `body` that serde_json stopped at
+fn error_offset(e: &serde_json::Error, body: &str) -> usize {
+    let line_start: usize = body
+        .split_inclusive('\n')
+        .take(e.line().saturating_sub(1))
+        .map(str::len)
+        .sum();
+    (line_start + e.column()).min(body.len())
+}
+
+///Follows the objects and arrays in `body` up to `offset` to find the field being parsed there,
+///like `data[3].node.title`. Anything after `offset` is ignored, so it works on bodies that
+///aren't valid JSON too.
+fn json_path(body: &str, offset: usize) -> String {
+    enum Frame {
+        //the key being parsed and whether the next string is a key
+        Object(Option<String>, bool),
+        Array(usize),
+    }
+    let bytes = &body.as_bytes()[..offset];
+    let mut stack = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => {
+                let start = i + 1;
+                i = start;
+                while i < bytes.len() && bytes[i] != b'"' {
+                    i += if bytes[i] == b'\\' { 2 } else { 1 };
+                }
+                if let Some(Frame::Object(key, expecting @ true)) = stack.last_mut() {
+                    *key = Some(String::from_utf8_lossy(&bytes[start..i.min(bytes.len())]).into());
+                    *expecting = false;
+                }
+            }
+            b'{' => stack.push(Frame::Object(None, true)),
+            b'[' => stack.push(Frame::Array(0)),
+            b'}' | b']' => {
+                stack.pop();
+            }
+            b',' => match stack.last_mut() {
+                Some(Frame::Object(key, expecting)) => {
+                    *key = None;
+                    *expecting = true;
+                }
+                Some(Frame::Array(index)) => *index += 1,
+                None => {}
+            },
+            _ => {}
+        }
+        i += 1;
+    }
+    let mut path = String::new();
+    for frame in stack {
+        match frame {
+            Frame::Object(Some(key), _) => {
+                if !path.is_empty() {
+                    path.push('.');
+                }
+                path.push_str(&key);
+            }
+            Frame::Object(None, _) => {}
+            Frame::Array(index) => path.push_str(&format!("[{}]", index)),
+        }
+    }
+    path
+}
+
+///The part of `body` around `offset`, so a huge response doesn't end up in the error
+fn snippet(body: &str, offset: usize) -> String {
+    if body.len() <= SNIPPET_CONTEXT * 2 {
+        return body.to_owned();
+    }
+    let mut start = offset.saturating_sub(SNIPPET_CONTEXT);
+    while !body.is_char_boundary(start) {
+        start -= 1;
+    }
+    let mut end = (offset + SNIPPET_CONTEXT).min(body.len());
+    while !body.is_char_boundary(end) {
+        end += 1;
+    }
+    format!(
+        "{}{}{}",
+        if start > 0 { "..." } else { "" },
+        &body[start..end],
+        if end < body.len() { "..." } else { "" }
+    )
+}
+
+///A `Request` error, so `?` works on reqwest results
+impl From<reqwest::Error> for MALError {
+    fn from(e: reqwest::Error) -> Self {
+        MALError::Request(e)
+    }
+}
+
+///A `Parse` error with an empty snippet, for JSON that isn't a response body or shouldn't end up
+///in an error, like stored tokens
+impl From<serde_json::Error> for MALError {
+    fn from(source: serde_json::Error) -> Self {
+        MALError::Parse {
+            source,
+            path: String::new(),
+            snippet: String::new(),
+        }
+    }
+}
+
+///An `Io` error without any context, `MALError::io` is used when there's something to say about
+///what was being done
+impl From<std::io::Error> for MALError {
+    fn from(source: std::io::Error) -> Self {
+        MALError::io("IO error", source)
+    }
+}
+
+///The body MAL sends back with an error
+#[derive(Deserialize)]
+pub(crate) struct ApiError {
+    pub error: String,
+    pub message: Option<String>,
+}
+
+impl ApiError {
+    ///Parses an error body like `{"error":"bad_request","message":"invalid q"}`. MAL sometimes
+    ///sends an empty message, which is treated as no message at all.
+    pub fn parse(body: &str) -> Option<Self> {
+        let mut e = serde_json::from_str::<ApiError>(body).ok()?;
+        if e.message.as_deref() == Some("") {
+            e.message = None;
+        }
+        Some(e)
+    }
+}
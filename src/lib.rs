@@ -5,22 +5,25 @@
 //! use lib_mal::ClientBuilder;
 //! use std::path::PathBuf;
 //! use lib_mal::MALError;
+//! use lib_mal::model::options::{StatusUpdate, WatchStatus};
 //!
 //!  async fn test() -> Result<(), MALError>{
 //!     //this has to exactly match a URI that's been registered with the MAL api
 //!     let redirect = "[YOUR_REDIRECT_URI_HERE]";
 //!     //the MALClient will attempt to refresh the cached access_token, if applicable
-//!     let mut client = ClientBuilder::new().secret("[YOUR_SECRET_HERE]".to_string()).caching(true).cache_dir(PathBuf::from("[SOME_CACHE_DIR]")).build_with_refresh().await?;
-//!     let (auth_url, challenge, state) = client.get_auth_parts();
+//!     let mut client = ClientBuilder::new().client_id("[YOUR_CLIENT_ID]".to_string()).caching(true).cache_dir(PathBuf::from("[SOME_CACHE_DIR]")).build_with_refresh().await?;
+//!     let (auth_url, challenge, state) = client.get_auth_parts(redirect);
 //!     //the user will have to have access to a browser in order to log in and give your application permission
 //!     println!("Go here to log in :) -> {}", auth_url);
 //!     //once the user has the URL, be sure to call client.auth to listen for the callback and complete the OAuth2 handshake
-//!     client.auth(&redirect, &challenge, &state).await?;
+//!     client.auth(redirect, &challenge, &state).await?;
 //!     //once the user is authorized, the API should be usable
 //!     //this will get the details, including all fields, for Mobile Suit Gundam
 //!     let anime = client.get_anime_details(80, None).await?;
 //!     //because so many fields are optional, a lot of the members of lib_mal::model::AnimeDetails are `Option`s
 //!     println!("{}: started airing on {}, ended on {}, ranked #{}", anime.show.title, anime.start_date.unwrap(), anime.end_date.unwrap(), anime.rank.unwrap());
+//!     //list updates only change the fields that are set on the `StatusUpdate`
+//!     client.update_user_anime_status(80, StatusUpdate::new().status(WatchStatus::Completed).score(9)).await?;
 //!     Ok(())
 //!}
 //!```
@@ -30,54 +33,19 @@ mod test;
 
 mod builder;
 mod client;
+mod error;
+mod listener;
 pub mod model;
+pub mod store;
 
 pub use builder::ClientBuilder;
-pub use client::MALClient;
-
-use serde::{Deserialize, Serialize};
-use std::error::Error;
-use std::fmt::{Debug, Display};
-
-#[derive(Serialize, Deserialize)]
-pub struct MALError {
-    pub error: String,
-    pub message: Option<String>,
-    pub info: Option<String>,
-}
-
-impl Display for MALError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "lib_mal encountered an error: {}", self.error)
-    }
-}
-
-impl Debug for MALError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "error: {} message: {} info: {}",
-            self.error,
-            self.message.as_ref().unwrap_or(&"none".to_string()),
-            self.info.as_ref().unwrap_or(&"none".to_string())
-        )
-    }
-}
-
-impl Error for MALError {}
-
-impl MALError {
-    pub fn new(msg: &str, error: &str, info: impl Into<Option<String>>) -> Self {
-        MALError {
-            error: error.to_owned(),
-            message: Some(msg.to_owned()),
-            info: info.into(),
-        }
-    }
-}
+pub use client::{MALClient, Tokens};
+pub use error::MALError;
+pub use listener::{CallbackListener, CancelHandle};
 
 pub mod prelude {
     pub use crate::builder::ClientBuilder;
-    pub use crate::client::MALClient;
+    pub use crate::client::{MALClient, Tokens};
     pub use crate::model::*;
+    pub use crate::store::TokenStore;
 }
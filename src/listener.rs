@@ -0,0 +1,254 @@
+//! The local server that receives the OAuth2 callback from MAL
+
+use futures::future::{self, Either};
+use simple_log::debug;
+use std::{error::Error, net::SocketAddr, thread, time::Duration};
+use subtle::ConstantTimeEq;
+use tiny_http::{Header, Response, Server};
+use tokio::sync::oneshot;
+
+use crate::MALError;
+
+///How often the callback listener checks whether it's still needed
+const LISTENER_POLL: Duration = Duration::from_millis(100);
+///How many unexpected requests the callback listener ignores before giving up
+const MAX_BOGUS_CALLBACKS: u32 = 10;
+
+///A listener waiting for the OAuth2 callback, created by `MALClient::start_listener` and consumed
+///by `MALClient::wait_for_callback`
+pub struct CallbackListener {
+    server: Server,
+    redirect_uri: String,
+    path: String,
+}
+
+impl CallbackListener {
+    ///Binds a server for `redirect_uri`, filling in the port the OS picked if it's 0
+    pub(crate) fn bind(
+        redirect_uri: &str,
+        bind: impl FnOnce(&str) -> Result<Server, Box<dyn Error + Send + Sync>>,
+    ) -> Result<Self, MALError> {
+        let redirect_uri = redirect_uri_with_scheme(redirect_uri);
+        let (scheme, rest) = redirect_uri
+            .split_once("://")
+            .unwrap_or(("http", &redirect_uri));
+        //server won't work if the url has the protocol or path in it
+        let (addr, path) = rest.split_at(rest.find('/').unwrap_or(rest.len()));
+        let server = bind(addr).map_err(|e| {
+            MALError::auth(
+                "bind_failed",
+                &format!("Unable to listen for the callback on {}: {}", addr, e),
+            )
+        })?;
+        let host = addr.rsplit_once(':').map_or(addr, |(host, _)| host);
+        let redirect_uri = format!(
+            "{}://{}:{}{}",
+            scheme,
+            host,
+            server.server_addr().port(),
+            path
+        );
+        Ok(CallbackListener {
+            server,
+            redirect_uri,
+            path: if path.is_empty() { "/" } else { path }.to_owned(),
+        })
+    }
+
+    ///The address the listener is bound to
+    pub fn local_addr(&self) -> SocketAddr {
+        self.server.server_addr()
+    }
+
+    ///The redirect_uri for this listener, including the port it ended up on
+    pub fn redirect_uri(&self) -> &str {
+        &self.redirect_uri
+    }
+
+    ///Waits for the callback for `state` and returns its code. The server is shut down before
+    ///this returns, whether it got the code or not.
+    pub(crate) async fn wait(
+        self,
+        state: &str,
+        page: Option<(String, String)>,
+        timeout: Option<Duration>,
+        cancel: Option<oneshot::Receiver<()>>,
+    ) -> Result<String, MALError> {
+        //the listener runs on its own thread so waiting for the user doesn't block the executor,
+        //and it stops once the receiver is dropped
+        let (tx, rx) = oneshot::channel();
+        let (done_tx, done_rx) = oneshot::channel::<()>();
+        let state = state.to_owned();
+        let (server, path) = (self.server, self.path);
+        thread::spawn(move || {
+            serve_callback(server, &path, &state, page, tx);
+            drop(done_tx);
+        });
+
+        let received = async {
+            match cancel {
+                Some(cancel) => match future::select(rx, cancel).await {
+                    Either::Left((received, _)) => Some(received),
+                    Either::Right((Ok(()), _)) => None,
+                    //the handle was dropped without cancelling
+                    Either::Right((Err(_), rx)) => Some(rx.await),
+                },
+                None => Some(rx.await),
+            }
+        };
+        let received = match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, received).await.ok(),
+            None => Some(received.await),
+        };
+        //wait for the thread to drop the server so it has stopped listening once this returns
+        let _ = done_rx.await;
+
+        match received {
+            Some(Some(received)) => received.map_err(|_| {
+                MALError::auth(
+                    "listener_closed",
+                    "The callback listener stopped unexpectedly",
+                )
+            })?,
+            Some(None) => Err(MALError::auth(
+                "auth_cancelled",
+                "Authorization was cancelled",
+            )),
+            None => Err(MALError::auth(
+                "auth_timeout",
+                "Timed out waiting for the user to authorize",
+            )),
+        }
+    }
+}
+
+///Cancels an `auth_cancellable` login, see `MALClient::auth_cancellable`
+pub struct CancelHandle {
+    tx: oneshot::Sender<()>,
+}
+
+impl CancelHandle {
+    pub(crate) fn new() -> (Self, oneshot::Receiver<()>) {
+        let (tx, rx) = oneshot::channel();
+        (CancelHandle { tx }, rx)
+    }
+
+    ///Stops waiting for the callback, making the login fail with an `auth_cancelled` error
+    pub fn cancel(self) {
+        let _ = self.tx.send(());
+    }
+}
+
+///Answers requests on `server` until the OAuth2 callback for `state` arrives on `path` and sends
+///back its code, or until nobody is waiting for it anymore
+fn serve_callback(
+    server: Server,
+    path: &str,
+    state: &str,
+    page: Option<(String, String)>,
+    tx: oneshot::Sender<Result<String, MALError>>,
+) {
+    let mut bogus = 0;
+    while !tx.is_closed() {
+        let i = match server.recv_timeout(LISTENER_POLL) {
+            Ok(Some(i)) => i,
+            Ok(None) => continue,
+            Err(e) => {
+                let _ = tx.send(Err(MALError::io("Unable to receive the callback", e)));
+                return;
+            }
+        };
+        let req_path = i.url().split('?').next().unwrap_or_default();
+        //the query has the auth code and state, so only the path is logged
+        debug!("callback request for {}", req_path);
+        if req_path != path {
+            //browsers ask for things like /favicon.ico too, those aren't the callback
+            let _ = i.respond(Response::from_string("Not found").with_status_code(404));
+            continue;
+        }
+        let code = parse_callback(i.url(), state);
+        if matches!(&code, Err(e) if e.error() == "state_mismatch") {
+            //anything that isn't the callback we're waiting for gets discarded, but only so many
+            //times so a misbehaving page can't keep the listener around forever
+            bogus += 1;
+            if bogus < MAX_BOGUS_CALLBACKS {
+                let _ =
+                    i.respond(Response::from_string("Unexpected request").with_status_code(400));
+                continue;
+            }
+        }
+        let response = match (&code, &page) {
+            (Err(e), _) => Response::from_string(format!(
+                "Unable to log in: {}. You can now close this window",
+                e.message().unwrap_or(e.error())
+            )),
+            (Ok(_), Some((body, content_type))) => {
+                let response = Response::from_string(body.as_str());
+                match Header::from_bytes("Content-Type", content_type.as_str()) {
+                    Ok(header) => response.with_header(header),
+                    Err(_) => response,
+                }
+            }
+            (Ok(_), None) => {
+                Response::from_string("You're logged in! You can now close this window")
+            }
+        };
+        if let Err(e) = i.respond(response) {
+            //the code is all that's needed, the browser not getting the page doesn't matter
+            debug!("Unable to respond to the callback: {}", e);
+        }
+        let _ = tx.send(code);
+        return;
+    }
+}
+
+///Pulls the authorization code out of the callback url MAL redirected the user to. Returns a
+///`state_mismatch` error if the callback isn't for this login attempt, and the error MAL sent if
+///the user denied access.
+pub(crate) fn parse_callback(url: &str, state: &str) -> Result<String, MALError> {
+    let mismatch = || {
+        MALError::auth(
+            "state_mismatch",
+            "The callback is for a different login attempt",
+        )
+    };
+    let url = reqwest::Url::parse("http://localhost")
+        .and_then(|base| base.join(url))
+        .map_err(|_| mismatch())?;
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(k, _)| k == name)
+            .map(|(_, v)| v.into_owned())
+    };
+    let received = param("state").unwrap_or_default();
+    if !bool::from(received.as_bytes().ct_eq(state.as_bytes())) {
+        return Err(mismatch());
+    }
+    if let Some(error) = param("error") {
+        let msg = if error == "access_denied" {
+            "Authorization denied by user"
+        } else {
+            "Authorization failed"
+        };
+        let description = param("error_description");
+        return Err(MALError::auth(
+            &error,
+            description.as_deref().unwrap_or(msg),
+        ));
+    }
+    param("code").ok_or_else(|| {
+        MALError::auth(
+            "missing_code",
+            &format!("Callback didn't include an authorization code: {}", url),
+        )
+    })
+}
+
+///Adds `http://` to redirect URIs without a scheme, since MAL compares the full URI
+pub(crate) fn redirect_uri_with_scheme(redirect_uri: &str) -> String {
+    if redirect_uri.starts_with("http://") || redirect_uri.starts_with("https://") {
+        redirect_uri.to_owned()
+    } else {
+        format!("http://{}", redirect_uri)
+    }
+}
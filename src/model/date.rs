@@ -0,0 +1,194 @@
+use chrono::{DateTime, Datelike, Duration, FixedOffset, NaiveDate, TimeZone, Weekday};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use std::str::FromStr;
+
+use super::options::ListDate;
+use super::{AnimeDetails, Broadcast, DayOfWeek};
+use crate::MALError;
+
+///A date from MAL that might only have a year, like `"2007"`, or a year and month, like
+///`"2007-10"`. Older shows often only have partial air dates.
+///
+///```
+/// use lib_mal::model::PartialDate;
+///
+/// let date: PartialDate = "2007-10".parse().unwrap();
+/// assert_eq!((date.year, date.month, date.day), (2007, Some(10), None));
+/// assert_eq!(date.to_naive_date(), None);
+///```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PartialDate {
+    pub year: i32,
+    pub month: Option<u32>,
+    pub day: Option<u32>,
+}
+
+impl PartialDate {
+    ///The full date, if MAL sent a day
+    pub fn to_naive_date(&self) -> Option<NaiveDate> {
+        NaiveDate::from_ymd_opt(self.year, self.month?, self.day?)
+    }
+}
+
+impl From<NaiveDate> for PartialDate {
+    fn from(date: NaiveDate) -> Self {
+        PartialDate {
+            year: date.year(),
+            month: Some(date.month()),
+            day: Some(date.day()),
+        }
+    }
+}
+
+impl FromStr for PartialDate {
+    type Err = MALError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            MALError::new(
+                "Dates must be YYYY, YYYY-MM or YYYY-MM-DD",
+                "invalid_date",
+                s.to_owned(),
+            )
+        };
+        let mut parts = s.split('-');
+        let year = parts
+            .next()
+            .filter(|y| y.len() == 4)
+            .and_then(|y| y.parse().ok())
+            .ok_or_else(invalid)?;
+        let mut next = |max: u32| match parts.next() {
+            None => Ok(None),
+            Some(part) => match part.parse() {
+                Ok(n) if part.len() == 2 && (1..=max).contains(&n) => Ok(Some(n)),
+                _ => Err(invalid()),
+            },
+        };
+        let date = PartialDate {
+            year,
+            month: next(12)?,
+            day: next(31)?,
+        };
+        if parts.next().is_some() || (date.day.is_some() && date.to_naive_date().is_none()) {
+            return Err(invalid());
+        }
+        Ok(date)
+    }
+}
+
+impl Display for PartialDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04}", self.year)?;
+        if let Some(month) = self.month {
+            write!(f, "-{:02}", month)?;
+            if let Some(day) = self.day {
+                write!(f, "-{:02}", day)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Serialize for PartialDate {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for PartialDate {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(|_| {
+            de::Error::invalid_value(de::Unexpected::Str(&s), &"YYYY, YYYY-MM or YYYY-MM-DD")
+        })
+    }
+}
+
+impl ListDate for PartialDate {
+    fn to_list_date(&self) -> String {
+        self.to_string()
+    }
+}
+
+impl DayOfWeek {
+    ///The chrono weekday, `None` for `Other`
+    pub fn to_weekday(self) -> Option<Weekday> {
+        match self {
+            DayOfWeek::Monday => Some(Weekday::Mon),
+            DayOfWeek::Tuesday => Some(Weekday::Tue),
+            DayOfWeek::Wednesday => Some(Weekday::Wed),
+            DayOfWeek::Thursday => Some(Weekday::Thu),
+            DayOfWeek::Friday => Some(Weekday::Fri),
+            DayOfWeek::Saturday => Some(Weekday::Sat),
+            DayOfWeek::Sunday => Some(Weekday::Sun),
+            DayOfWeek::Other => None,
+        }
+    }
+}
+
+impl Broadcast {
+    ///The next time the show airs at or after `now`, in the same time zone as `now`. `None` if
+    ///MAL doesn't know the day or time it airs.
+    pub fn next_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let day = self.day_of_the_week.to_weekday()?;
+        let time = self.start_time?;
+        //Japan doesn't have daylight saving time, so a fixed offset is enough
+        let jst = FixedOffset::east_opt(9 * 3600)?;
+        let local = now.with_timezone(&jst);
+        let days_ahead =
+            (7 + day.num_days_from_monday() - local.weekday().num_days_from_monday()) % 7;
+        let date = local.date_naive() + Duration::days(days_ahead.into());
+        let mut next = jst.from_local_datetime(&date.and_time(time)).single()?;
+        if next < local {
+            next += Duration::days(7);
+        }
+        Some(next.with_timezone(&now.timezone()))
+    }
+}
+
+impl AnimeDetails {
+    ///The next time the show airs at or after `now`, see `Broadcast::next_after`. `None` if the
+    ///broadcast wasn't requested or MAL doesn't know when the show airs.
+    ///
+    ///```
+    /// use chrono::Utc;
+    /// use lib_mal::model::AnimeDetails;
+    ///
+    /// fn airs_today(anime: &AnimeDetails) -> bool {
+    ///     let now = Utc::now();
+    ///     anime
+    ///         .next_broadcast(now)
+    ///         .map_or(false, |next| next - now < chrono::Duration::days(1))
+    /// }
+    ///```
+    pub fn next_broadcast<Tz: TimeZone>(&self, now: DateTime<Tz>) -> Option<DateTime<Tz>> {
+        self.broadcast.as_ref()?.next_after(&now)
+    }
+}
+
+///Broadcast times are `HH:MM`. One that can't be read is treated like a show without a time
+///rather than failing the whole response.
+pub(super) mod broadcast_time {
+    use chrono::NaiveTime;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    const FORMAT: &str = "%H:%M";
+
+    pub fn serialize<S: Serializer>(
+        time: &Option<NaiveTime>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match time {
+            Some(time) => serializer.collect_str(&time.format(FORMAT)),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<NaiveTime>, D::Error> {
+        let time = Option::<String>::deserialize(deserializer)?;
+        Ok(time.and_then(|t| NaiveTime::parse_from_str(&t, FORMAT).ok()))
+    }
+}
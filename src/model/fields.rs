@@ -1,8 +1,22 @@
+use crate::MALError;
 use bitflags::bitflags;
 use std::fmt::Display;
+use std::str::FromStr;
 
 bitflags! {
-    pub struct AnimeFields: u32 {
+    ///The fields MAL sends for an anime. `ALL` is every top level field.
+    ///
+    ///The `MyListStatus*` flags ask for parts of `my_list_status` that MAL leaves out unless
+    ///they're named, like the tags and comments. Each of them has the `MyListStatus` bit too, so
+    ///taking one away with `-` takes away `my_list_status` as well.
+    ///
+    ///```
+    /// use lib_mal::model::fields::AnimeFields;
+    ///
+    /// let fields = AnimeFields::Title | AnimeFields::MyListStatusTags | AnimeFields::MyListStatusComments;
+    /// assert_eq!(fields.to_string(), "title,my_list_status{tags,comments}");
+    ///```
+    pub struct AnimeFields: u64 {
         const ID                        = 0b0000_0000_0000_0000_0000_0000_0000_0001;
         const Title                     = 0b0000_0000_0000_0000_0000_0000_0000_0010;
         const MainPicture               = 0b0000_0000_0000_0000_0000_0000_0000_0100;
@@ -35,7 +49,14 @@ bitflags! {
         const Recommendations           = 0b0010_0000_0000_0000_0000_0000_0000_0000;
         const Studios                   = 0b0100_0000_0000_0000_0000_0000_0000_0000;
         const Statistics                = 0b1000_0000_0000_0000_0000_0000_0000_0000;
-        const ALL                       = 0b1111_1111_1111_1111_1111_1111_1111_1111;
+        //bits 32 to 36 are the my_list_status sub-fields below
+        const Videos                    = 1 << 37;
+        const ALL                       = 0b1111_1111_1111_1111_1111_1111_1111_1111 | Self::Videos.bits;
+        const MyListStatusPriority          = 1 << 32 | Self::MyListStatus.bits;
+        const MyListStatusNumTimesRewatched = 1 << 33 | Self::MyListStatus.bits;
+        const MyListStatusRewatchValue      = 1 << 34 | Self::MyListStatus.bits;
+        const MyListStatusTags              = 1 << 35 | Self::MyListStatus.bits;
+        const MyListStatusComments          = 1 << 36 | Self::MyListStatus.bits;
     }
 }
 
@@ -91,6 +112,7 @@ generate_get_anime_fields_names! {
     recommendations => "recommendations",
     studios => "studios",
     statistics => "statistics",
+    videos => "videos",
 }
 
 macro_rules! bits {
@@ -138,10 +160,144 @@ bits!(
     recommendations => Recommendations,
     studios => Studios,
     statistics => Statistics,
+    videos => Videos,
 );
 
+///The `my_list_status` sub-fields, in the order they're written inside the braces
+const LIST_STATUS_FIELDS: [(AnimeFields, &str); 5] = [
+    (AnimeFields::MyListStatusPriority, "priority"),
+    (
+        AnimeFields::MyListStatusNumTimesRewatched,
+        "num_times_rewatched",
+    ),
+    (AnimeFields::MyListStatusRewatchValue, "rewatch_value"),
+    (AnimeFields::MyListStatusTags, "tags"),
+    (AnimeFields::MyListStatusComments, "comments"),
+];
+
+impl AnimeFields {
+    ///Every `MyListStatus*` sub-field
+    pub const MY_LIST_STATUS_ALL: AnimeFields =
+        AnimeFields::from_bits_truncate(0b1_1111 << 32 | AnimeFields::MyListStatus.bits);
+
+    ///Every top level field but `fields`, the same as `AnimeFields::ALL - fields`
+    ///
+    ///`!fields` is everything but `fields` as well, but it also has the `my_list_status`
+    ///sub-fields.
+    ///
+    ///```
+    /// use lib_mal::model::fields::AnimeFields;
+    ///
+    /// let fields = AnimeFields::except(AnimeFields::Synopsis | AnimeFields::Background);
+    /// assert!(!fields.to_string().contains("synopsis"));
+    ///```
+    pub fn except(fields: AnimeFields) -> AnimeFields {
+        AnimeFields::ALL - fields
+    }
+
+    ///Iterates over the names of the contained top level fields, the same ones as
+    ///`get_fields_names`
+    pub fn iter_names(self) -> impl Iterator<Item = &'static str> {
+        self.get_fields_names().into_iter()
+    }
+
+    ///Iterates over the contained top level fields one flag at a time
+    pub fn iter(self) -> impl Iterator<Item = AnimeFields> {
+        (0..64)
+            .map(|bit| AnimeFields::from_bits_truncate(1 << bit) & AnimeFields::ALL)
+            .filter(move |field| !field.is_empty() && self.contains(*field))
+    }
+
+    ///The top level field called `name`
+    fn from_name(name: &str) -> Option<AnimeFields> {
+        AnimeFields::ALL
+            .iter()
+            .find(|field| field.get_fields_names() == [name])
+    }
+
+    ///Returns the names of the contained `my_list_status` sub-fields
+    pub fn get_list_status_fields_names(self) -> Vec<&'static str> {
+        LIST_STATUS_FIELDS
+            .iter()
+            .filter(|(flag, _)| self.contains(*flag))
+            .map(|(_, name)| *name)
+            .collect()
+    }
+}
+
 impl Display for AnimeFields {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.get_fields_names().join(","))
+        let sub_fields = self.get_list_status_fields_names();
+        for (i, name) in self.get_fields_names().iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(name)?;
+            if *name == "my_list_status" && !sub_fields.is_empty() {
+                write!(f, "{{{}}}", sub_fields.join(","))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+///Parses fields the way `Display` writes them, like `"rank,mean,my_list_status{tags}"`
+impl FromStr for AnimeFields {
+    type Err = MALError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let unknown = |name: &str, expected: Vec<&str>| {
+            MALError::new(
+                &format!(
+                    "Unknown field `{}`, expected one of {}",
+                    name,
+                    expected.join(", ")
+                ),
+                "invalid_fields",
+                s.to_owned(),
+            )
+        };
+        //commas inside the braces separate sub-fields, not fields
+        let mut parts = vec![];
+        let (mut depth, mut start) = (0, 0);
+        for (i, c) in s.char_indices() {
+            match c {
+                '{' => depth += 1,
+                '}' => depth -= 1,
+                ',' if depth == 0 => {
+                    parts.push(&s[start..i]);
+                    start = i + 1;
+                }
+                _ => {}
+            }
+        }
+        parts.push(&s[start..]);
+
+        let mut fields = AnimeFields::empty();
+        for part in parts.into_iter().map(str::trim).filter(|p| !p.is_empty()) {
+            if let Some(sub_fields) = part
+                .strip_prefix("my_list_status{")
+                .and_then(|p| p.strip_suffix('}'))
+            {
+                fields |= AnimeFields::MyListStatus;
+                for sub_field in sub_fields
+                    .split(',')
+                    .map(str::trim)
+                    .filter(|f| !f.is_empty())
+                {
+                    fields |= LIST_STATUS_FIELDS
+                        .iter()
+                        .find(|(_, name)| *name == sub_field)
+                        .map(|(flag, _)| *flag)
+                        .ok_or_else(|| {
+                            unknown(sub_field, AnimeFields::all().get_list_status_fields_names())
+                        })?;
+                }
+            } else {
+                fields |= AnimeFields::from_name(part)
+                    .ok_or_else(|| unknown(part, AnimeFields::ALL.get_fields_names()))?;
+            }
+        }
+        Ok(fields)
     }
 }
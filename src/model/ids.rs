@@ -0,0 +1,53 @@
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+
+///Makes a newtype for one kind of MAL id, so an id for one thing can't be passed where another is
+///expected. They're (de)serialized and displayed as the bare number, and `u32`s convert into
+///them so methods taking `impl Into<AnimeId>` still accept literals.
+macro_rules! id_type {
+    ($($(#[$meta:meta])* $name:ident),* $(,)?) => {$(
+        $(#[$meta])*
+        #[derive(
+            Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord,
+        )]
+        #[serde(transparent)]
+        pub struct $name(pub u32);
+
+        impl From<u32> for $name {
+            fn from(id: u32) -> Self {
+                $name(id)
+            }
+        }
+
+        impl From<$name> for u32 {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl PartialEq<u32> for $name {
+            fn eq(&self, other: &u32) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+    )*};
+}
+
+id_type! {
+    ///The id of an anime, like `80` for Mobile Suit Gundam
+    AnimeId,
+    ///The id of a manga
+    MangaId,
+    ///The id of a forum topic
+    TopicId,
+    ///The id of a forum board
+    BoardId,
+    ///The id of a forum subboard. Subboards are numbered separately from boards.
+    SubboardId,
+}
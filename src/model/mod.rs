@@ -1,130 +1,755 @@
-use serde::{Deserialize, Serialize};
+//! The types MAL's responses are parsed into
+//!
+//! Enums for MAL's string values, like `MediaType` or `WatchStatus`, have an `Other` variant that
+//! keeps values MAL adds in the future, so a new value doesn't fail parsing the whole response.
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::fmt::Display;
+use std::ops::Deref;
+use std::time::Duration;
+
+///Makes an enum for one of MAL's string values, with an `Other` variant that keeps values it
+///doesn't know about. It's parsed and (de)serialized as the string, and displayed as the label
+///after `as` if there is one.
+macro_rules! string_enum {
+    (
+        $(#[$meta:meta])*
+        pub enum $name:ident {
+            $($variant:ident => $value:literal $(as $label:literal)?),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        pub enum $name {
+            $($variant,)*
+            Other(String),
+        }
+
+        impl $name {
+            ///The value MAL uses, like `"finished_airing"`
+            pub fn as_str(&self) -> &str {
+                match self {
+                    $(Self::$variant => $value,)*
+                    Self::Other(s) => s,
+                }
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let me = match self {
+                    $(Self::$variant => label_or!($value $(, $label)?),)*
+                    Self::Other(s) => s,
+                };
+                write!(f, "{}", me)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(s: &str) -> Self {
+                match s {
+                    $($value => Self::$variant,)*
+                    other => Self::Other(other.to_owned()),
+                }
+            }
+        }
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_str(self.as_str())
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                Ok(String::deserialize(deserializer)?.as_str().into())
+            }
+        }
+    };
+}
 
+macro_rules! label_or {
+    ($value:literal) => {
+        $value
+    };
+    ($value:literal, $label:literal) => {
+        $label
+    };
+}
+
+#[cfg(feature = "chrono")]
+mod date;
 #[allow(non_upper_case_globals)]
 pub mod fields;
+mod ids;
 pub mod options;
 
+#[cfg(feature = "chrono")]
+pub use date::PartialDate;
+pub use ids::{AnimeId, BoardId, MangaId, SubboardId, TopicId};
+
+#[allow(deprecated)]
 pub use options::StatusBuilder;
+pub use options::{Season, StatusUpdate, WatchStatus};
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AnimeList {
     pub data: Vec<ListNode>,
-    paging: HashMap<String, Value>,
-    pub season: Option<HashMap<String, Value>>,
+    #[serde(default)]
+    pub paging: Paging,
+    pub season: Option<StartSeason>,
+}
+
+impl AnimeList {
+    ///Returns the URL of the next page of results, if there is one
+    pub fn next_page(&self) -> Option<&str> {
+        self.paging.next.as_deref()
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct ListNode {
-    pub node: Anime,
+    ///Only has the fields that were asked for, besides the `id`, `title` and `main_picture`
+    pub node: AnimeDetails,
     pub list_status: Option<ListStatus>,
-    pub ranking: Option<HashMap<String, u32>>,
+    pub ranking: Option<Ranking>,
+    ///Fields next to `node` this struct doesn't have
+    #[cfg(feature = "raw-extras")]
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+///Where an anime is in the ranking returned by `get_anime_ranking`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Ranking {
+    pub rank: u32,
+    pub previous_rank: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ListStatus {
-    pub status: Option<String>,
+    pub status: Option<WatchStatus>,
     pub num_episodes_watched: Option<u32>,
     pub score: Option<u8>,
+    #[cfg(feature = "chrono")]
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[cfg(not(feature = "chrono"))]
     pub updated_at: Option<String>,
     pub is_rewatching: Option<bool>,
+    #[cfg(feature = "chrono")]
+    pub start_date: Option<PartialDate>,
+    #[cfg(not(feature = "chrono"))]
+    pub start_date: Option<String>,
+    #[cfg(feature = "chrono")]
+    pub finish_date: Option<PartialDate>,
+    #[cfg(not(feature = "chrono"))]
+    pub finish_date: Option<String>,
     pub priority: Option<u32>,
+    pub num_times_rewatched: Option<u32>,
     pub rewatch_value: Option<u32>,
     pub tags: Option<Vec<String>>,
     pub comments: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct Anime {
-    pub id: u32,
+    pub id: AnimeId,
     pub title: String,
-    pub main_picture: HashMap<String, Value>,
+    ///`None` when MAL has no picture for the anime
+    pub main_picture: Option<Picture>,
+    ///Fields MAL sent that neither this struct nor the `AnimeDetails` it's in have. Useful for
+    ///getting at something MAL added before this crate has caught up.
+    #[cfg(feature = "raw-extras")]
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct AnimeDetails {
     #[serde(flatten)]
     pub show: Anime,
     pub alternative_titles: Option<AlternativeTitles>,
+    #[cfg(feature = "chrono")]
+    pub start_date: Option<PartialDate>,
+    #[cfg(not(feature = "chrono"))]
     pub start_date: Option<String>,
+    #[cfg(feature = "chrono")]
+    pub end_date: Option<PartialDate>,
+    #[cfg(not(feature = "chrono"))]
     pub end_date: Option<String>,
     pub synopsis: Option<String>,
     pub mean: Option<f32>,
     pub rank: Option<u32>,
+    pub popularity: Option<u32>,
     pub num_list_users: Option<u32>,
     pub num_scoring_users: Option<u32>,
-    pub nsfw: Option<String>,
+    pub nsfw: Option<Nsfw>,
+    #[cfg(feature = "chrono")]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[cfg(not(feature = "chrono"))]
     pub created_at: Option<String>,
+    #[cfg(feature = "chrono")]
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[cfg(not(feature = "chrono"))]
     pub updated_at: Option<String>,
-    pub media_type: Option<String>,
-    pub status: Option<String>,
-    pub genres: Option<Vec<HashMap<String, Value>>>,
+    pub media_type: Option<MediaType>,
+    pub status: Option<AiringStatus>,
+    pub genres: Option<Vec<Genre>>,
     pub my_list_status: Option<ListStatus>,
+    ///`None` when MAL doesn't know yet, which it sends as 0
+    #[serde(default, deserialize_with = "zero_as_none")]
     pub num_episodes: Option<u32>,
-    pub start_season: Option<HashMap<String, Value>>,
-    pub broadcast: Option<HashMap<String, String>>,
-    pub source: Option<String>,
-    pub average_episode_duration: Option<u32>,
-    pub rating: Option<String>,
-    pub pictures: Option<Vec<HashMap<String, String>>>,
+    pub start_season: Option<StartSeason>,
+    pub broadcast: Option<Broadcast>,
+    pub source: Option<Source>,
+    ///MAL sends this in seconds
+    #[serde(default, with = "seconds")]
+    pub average_episode_duration: Option<Duration>,
+    pub rating: Option<Rating>,
+    pub pictures: Option<Vec<Picture>>,
     pub background: Option<String>,
-    pub related_anime: Option<Vec<Related>>,
+    pub related_anime: Option<Vec<RelatedAnime>>,
     pub related_manga: Option<Vec<HashMap<String, Value>>>,
-    pub recommendations: Option<Vec<Recommnendation>>,
-    pub studios: Option<Vec<HashMap<String, Value>>>,
-    pub statistics: Option<Stats>,
+    pub recommendations: Option<Vec<Recommendation>>,
+    pub studios: Option<Vec<Studio>>,
+    pub statistics: Option<Statistics>,
+    pub videos: Option<Vec<Video>>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Stats {
-    pub status: HashMap<String, String>,
-    pub num_list_users: u32,
+///Lets the anime's `id`, `title` and `main_picture` be used as if they were fields of the details
+impl Deref for AnimeDetails {
+    type Target = Anime;
+
+    fn deref(&self) -> &Anime {
+        &self.show
+    }
+}
+
+impl AnimeDetails {
+    ///The title in `language`, falling back to the main title when there isn't one. The
+    ///alternative titles have to be requested for anything but the main title.
+    ///
+    ///```
+    /// use lib_mal::model::{AnimeDetails, TitleLanguage};
+    ///
+    /// fn show(anime: &AnimeDetails) {
+    ///     println!("{}", anime.title_in(TitleLanguage::English));
+    /// }
+    ///```
+    pub fn title_in(&self, language: TitleLanguage) -> &str {
+        let titles = self.alternative_titles.as_ref();
+        let title = match language {
+            TitleLanguage::English => titles.and_then(|t| t.en.as_deref()),
+            TitleLanguage::Japanese => titles.and_then(|t| t.ja.as_deref()),
+            TitleLanguage::Romaji => None,
+        };
+        //MAL sends an empty string for titles it doesn't have
+        title.filter(|t| !t.is_empty()).unwrap_or(&self.show.title)
+    }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+///The URLs of a picture in two sizes. MAL leaves out `large` for some pictures.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Picture {
+    pub medium: Option<String>,
+    pub large: Option<String>,
+}
+
+///A promotional video for an anime, usually on YouTube
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Video {
+    pub id: u32,
+    #[serde(default)]
+    pub title: String,
+    pub url: String,
+    pub thumbnail: Option<String>,
+    ///MAL sends these as unix timestamps, which are kept as seconds without the `chrono` feature
+    #[cfg(feature = "chrono")]
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub created_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[cfg(not(feature = "chrono"))]
+    pub created_at: Option<i64>,
+    #[cfg(feature = "chrono")]
+    #[serde(default, with = "chrono::serde::ts_seconds_option")]
+    pub updated_at: Option<chrono::DateTime<chrono::Utc>>,
+    #[cfg(not(feature = "chrono"))]
+    pub updated_at: Option<i64>,
+}
+
+///A genre on MAL. The `id` stays the same even if the name changes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Genre {
+    pub id: u32,
+    pub name: String,
+}
+
+///A studio that worked on an anime
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Studio {
+    pub id: u32,
+    pub name: String,
+}
+
+///When a show airs each week, in Japan Standard Time
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Broadcast {
+    pub day_of_the_week: DayOfWeek,
+    #[cfg(feature = "chrono")]
+    #[serde(default, with = "date::broadcast_time")]
+    pub start_time: Option<chrono::NaiveTime>,
+    #[cfg(not(feature = "chrono"))]
+    pub start_time: Option<String>,
+}
+
+///The day a show airs on. MAL uses `Other` for shows without a regular day.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum DayOfWeek {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+    #[serde(other)]
+    Other,
+}
+
+///The season an anime started airing in, also used for the season `get_seasonal_anime` returned
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StartSeason {
+    pub year: u32,
+    pub season: Season,
+}
+
+///How many users have an anime on their list
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Statistics {
+    #[serde(default, deserialize_with = "number_or_string")]
+    pub num_list_users: u64,
+    #[serde(default)]
+    pub status: StatusCounts,
+}
+
+#[deprecated(since = "0.6.0", note = "renamed to `Statistics`")]
+pub type Stats = Statistics;
+
+///How many users have an anime on their list with each status
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+#[serde(default)]
+pub struct StatusCounts {
+    #[serde(deserialize_with = "number_or_string")]
+    pub watching: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub completed: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub on_hold: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub dropped: u64,
+    #[serde(deserialize_with = "number_or_string")]
+    pub plan_to_watch: u64,
+}
+
+fn zero_as_none<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<u32>, D::Error> {
+    Ok(Option::<u32>::deserialize(deserializer)?.filter(|n| *n > 0))
+}
+
+///A duration in whole seconds
+mod seconds {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        duration: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match duration {
+            Some(d) => serializer.serialize_u64(d.as_secs()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        Ok(Option::<u64>::deserialize(deserializer)?.map(Duration::from_secs))
+    }
+}
+
+///MAL sends some counts as strings, like `"12345"`, so either is accepted
+fn number_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Count {
+        Number(u64),
+        String(String),
+    }
+    match Count::deserialize(deserializer)? {
+        Count::Number(n) => Ok(n),
+        Count::String(s) => s.parse().map_err(|_| {
+            serde::de::Error::invalid_value(serde::de::Unexpected::Str(&s), &"a count")
+        }),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct AlternativeTitles {
+    #[serde(default)]
     pub synonyms: Vec<String>,
-    #[serde(flatten)]
-    pub languages: HashMap<String, String>,
+    pub en: Option<String>,
+    pub ja: Option<String>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Related {
+///A language an anime's title can be shown in, see `AnimeDetails::title_in`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TitleLanguage {
+    English,
+    Japanese,
+    ///The main title, which is normally the romanized Japanese one
+    Romaji,
+}
+
+///Another anime in the same franchise
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct RelatedAnime {
     pub node: Anime,
-    pub relation_type: String,
+    pub relation_type: RelationType,
+    ///The relation as MAL shows it, like `"Side story"`
+    #[serde(default)]
     pub relation_type_formatted: String,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Recommnendation {
+#[deprecated(since = "0.6.0", note = "renamed to `RelatedAnime`")]
+pub type Related = RelatedAnime;
+
+string_enum! {
+    ///How a related anime is connected to the one it's related to
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum RelationType {
+        Sequel => "sequel",
+        Prequel => "prequel",
+        AlternativeSetting => "alternative_setting",
+        AlternativeVersion => "alternative_version",
+        SideStory => "side_story",
+        ParentStory => "parent_story",
+        Summary => "summary",
+        FullStory => "full_story",
+        Character => "character",
+        SpinOff => "spin_off",
+    }
+}
+
+string_enum! {
+    ///Whether an anime is safe for work. `White` is safe, `Gray` may not be and `Black` isn't.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Nsfw {
+        White => "white",
+        Gray => "gray",
+        Black => "black",
+    }
+}
+
+impl Nsfw {
+    ///Whether the anime is safe for work, which is only true for `White`
+    pub fn is_safe(&self) -> bool {
+        *self == Nsfw::White
+    }
+}
+
+string_enum! {
+    ///What an anime was adapted from. It's displayed the way MAL shows it, like "Light novel".
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum Source {
+        Original => "original" as "Original",
+        Manga => "manga" as "Manga",
+        FourKomaManga => "4_koma_manga" as "4-koma manga",
+        WebManga => "web_manga" as "Web manga",
+        DigitalManga => "digital_manga" as "Digital manga",
+        Novel => "novel" as "Novel",
+        LightNovel => "light_novel" as "Light novel",
+        WebNovel => "web_novel" as "Web novel",
+        VisualNovel => "visual_novel" as "Visual novel",
+        Game => "game" as "Game",
+        CardGame => "card_game" as "Card game",
+        Book => "book" as "Book",
+        PictureBook => "picture_book" as "Picture book",
+        Radio => "radio" as "Radio",
+        Music => "music" as "Music",
+        MixedMedia => "mixed_media" as "Mixed media",
+    }
+}
+
+string_enum! {
+    ///An anime's age rating. Ratings are ordered from `G` to `Rx`, with `Other` after all of them
+    ///so a value MAL adds later isn't let through a filter like `rating <= Rating::Pg13`.
+    #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+    pub enum Rating {
+        G => "g",
+        Pg => "pg",
+        Pg13 => "pg_13",
+        R => "r",
+        RPlus => "r+",
+        Rx => "rx",
+    }
+}
+
+string_enum! {
+    ///Whether an anime has started or finished airing
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum AiringStatus {
+        FinishedAiring => "finished_airing",
+        CurrentlyAiring => "currently_airing",
+        NotYetAired => "not_yet_aired",
+    }
+}
+
+string_enum! {
+    ///The kind of anime, like a TV series or a movie
+    #[derive(Debug, Clone, PartialEq, Eq, Hash)]
+    pub enum MediaType {
+        Tv => "tv",
+        Ova => "ova",
+        Movie => "movie",
+        Special => "special",
+        Ona => "ona",
+        Music => "music",
+        Unknown => "unknown",
+    }
+}
+
+///An anime users recommend to people who liked the one it's on, `num_recommendations` is how
+///many of them did
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct Recommendation {
     pub node: Anime,
     pub num_recommendations: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[deprecated(since = "0.6.0", note = "renamed to `Recommendation`")]
+pub type Recommnendation = Recommendation;
+
+///A MAL user. Everything but `id`, `name` and `joined_at` is only sent when it's on the
+///user's profile, and `anime_statistics` only when it's requested.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
 pub struct User {
     pub id: u32,
     pub name: String,
-    pub location: String,
+    pub picture: Option<String>,
+    pub gender: Option<String>,
+    #[cfg(feature = "chrono")]
+    pub birthday: Option<PartialDate>,
+    #[cfg(not(feature = "chrono"))]
+    pub birthday: Option<String>,
+    pub location: Option<String>,
+    #[cfg(feature = "chrono")]
+    pub joined_at: chrono::DateTime<chrono::Utc>,
+    #[cfg(not(feature = "chrono"))]
     pub joined_at: String,
-    pub anime_statistics: HashMap<String, f32>,
+    pub anime_statistics: Option<AnimeStatistics>,
+    pub time_zone: Option<String>,
+    pub is_supporter: Option<bool>,
+    ///Fields MAL sent this struct doesn't have
+    #[cfg(feature = "raw-extras")]
+    #[serde(flatten)]
+    pub extra: serde_json::Map<String, Value>,
 }
 
-//TODO: Improve struct coverage for forum fucntions
-#[derive(Serialize, Deserialize, Debug)]
+///Totals for a user's anime list. Days are the time spent watching the shows in each status.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct AnimeStatistics {
+    pub num_items_watching: u32,
+    pub num_items_completed: u32,
+    pub num_items_on_hold: u32,
+    pub num_items_dropped: u32,
+    pub num_items_plan_to_watch: u32,
+    pub num_items: u32,
+    pub num_days_watched: f32,
+    pub num_days_watching: f32,
+    pub num_days_completed: f32,
+    pub num_days_on_hold: f32,
+    pub num_days_dropped: f32,
+    pub num_days: f32,
+    pub num_episodes: u32,
+    pub num_times_rewatched: u32,
+    pub mean_score: f32,
+}
+
+///Every forum board on MAL, grouped into categories
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct ForumBoards {
-    pub categories: Vec<HashMap<String, Value>>,
+    pub categories: Vec<ForumCategory>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForumCategory {
+    pub title: String,
+    #[serde(default)]
+    pub boards: Vec<ForumBoard>,
+}
+
+///A board's `id` is what `get_forum_topics` takes as the `board_id`
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForumBoard {
+    pub id: BoardId,
+    pub title: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub subboards: Vec<Subboard>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Subboard {
+    pub id: SubboardId,
+    pub title: String,
+}
+
+///A page of posts from a forum topic, and the topic's poll if it has one
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(from = "TopicPage", into = "TopicPage")]
 pub struct TopicDetails {
-    pub data: Vec<HashMap<String, Value>>,
-    pub paging: HashMap<String, Value>,
+    pub title: String,
+    pub posts: Vec<Post>,
+    pub poll: Option<Poll>,
+    pub paging: Paging,
+}
+
+impl TopicDetails {
+    ///Returns the URL of the next page of posts, if there is one
+    pub fn next_page(&self) -> Option<&str> {
+        self.paging.next.as_deref()
+    }
+}
+
+///MAL nests everything but the paging under `data`
+#[derive(Serialize, Deserialize, Clone)]
+struct TopicPage {
+    data: TopicData,
+    #[serde(default)]
+    paging: Paging,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct TopicData {
+    title: String,
+    #[serde(default)]
+    posts: Vec<Post>,
+    poll: Option<Poll>,
+}
+
+impl From<TopicPage> for TopicDetails {
+    fn from(page: TopicPage) -> Self {
+        TopicDetails {
+            title: page.data.title,
+            posts: page.data.posts,
+            poll: page.data.poll,
+            paging: page.paging,
+        }
+    }
+}
+
+impl From<TopicDetails> for TopicPage {
+    fn from(details: TopicDetails) -> Self {
+        TopicPage {
+            data: TopicData {
+                title: details.title,
+                posts: details.posts,
+                poll: details.poll,
+            },
+            paging: details.paging,
+        }
+    }
+}
+
+///A post in a forum topic. `number` is its position in the topic, starting at 1, and `body`
+///and `signature` are BBCode.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Post {
+    pub id: u32,
+    pub number: u32,
+    #[cfg(feature = "chrono")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[cfg(not(feature = "chrono"))]
+    pub created_at: String,
+    pub created_by: PostAuthor,
+    pub body: String,
+    #[serde(default)]
+    pub signature: String,
+}
+
+///`forum_avator` is the URL of the author's forum avatar, spelt the way MAL spells it
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PostAuthor {
+    pub id: u32,
+    pub name: Option<String>,
+    #[serde(default)]
+    pub forum_avator: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Poll {
+    pub id: u32,
+    pub question: String,
+    ///Whether the poll is closed to new votes
+    #[serde(default)]
+    pub close: bool,
+    pub options: Vec<PollOption>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PollOption {
+    pub id: u32,
+    pub text: String,
+    pub votes: u32,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+///A page of forum topics
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 pub struct ForumTopics {
-    pub data: Vec<HashMap<String, Value>>,
-    pub paging: Vec<HashMap<String, Value>>,
+    pub data: Vec<ForumTopic>,
+    #[serde(default)]
+    pub paging: Paging,
+}
+
+impl ForumTopics {
+    ///Returns the URL of the next page of results, if there is one
+    pub fn next_page(&self) -> Option<&str> {
+        self.paging.next.as_deref()
+    }
+}
+
+///URLs of the pages before and after the current one
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, Hash)]
+pub struct Paging {
+    pub previous: Option<String>,
+    pub next: Option<String>,
+}
+
+///A topic's `id` is what `get_forum_topic_detail` takes
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForumTopic {
+    pub id: TopicId,
+    pub title: String,
+    #[cfg(feature = "chrono")]
+    pub created_at: chrono::DateTime<chrono::Utc>,
+    #[cfg(not(feature = "chrono"))]
+    pub created_at: String,
+    pub created_by: ForumUser,
+    pub number_of_posts: u32,
+    #[cfg(feature = "chrono")]
+    pub last_post_created_at: chrono::DateTime<chrono::Utc>,
+    #[cfg(not(feature = "chrono"))]
+    pub last_post_created_at: String,
+    pub last_post_created_by: Option<ForumUser>,
+    #[serde(default)]
+    pub is_locked: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ForumUser {
+    pub id: u32,
+    pub name: String,
 }
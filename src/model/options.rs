@@ -1,6 +1,9 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
 use std::fmt::Display;
 
-#[derive(Debug)]
+use crate::MALError;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum RankingType {
     All,
     Airing,
@@ -30,7 +33,8 @@ impl Display for RankingType {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
 pub enum Season {
     Winter,
     Spring,
@@ -50,118 +54,76 @@ impl Display for Season {
     }
 }
 
-#[derive(Debug)]
-pub enum Status {
+///The status of an anime on the user's list
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum WatchStatus {
     Watching,
     Completed,
     OnHold,
     Dropped,
     PlanToWatch,
+    Other(String),
 }
 
-impl Display for Status {
+#[deprecated(since = "0.6.0", note = "renamed to `WatchStatus`")]
+pub type Status = WatchStatus;
+
+impl Display for WatchStatus {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let me = match self {
-            Self::Watching => "watching".to_owned(),
-            Self::Completed => "completed".to_owned(),
-            Self::OnHold => "on_hold".to_owned(),
-            Self::Dropped => "dropped".to_owned(),
-            Self::PlanToWatch => "plan_to_watch".to_owned(),
+            Self::Watching => "watching",
+            Self::Completed => "completed",
+            Self::OnHold => "on_hold",
+            Self::Dropped => "dropped",
+            Self::PlanToWatch => "plan_to_watch",
+            Self::Other(s) => s,
         };
         write!(f, "{}", me)
     }
 }
 
-pub trait Params {
-    fn get_params<'a>(self) -> Vec<(&'a str, String)>;
-}
-
-#[derive(Debug, Default)]
-pub struct StatusUpdate {
-    status: Option<Status>,
-    is_rewatching: Option<bool>,
-    score: Option<u8>,
-    num_watched_episodes: Option<u32>,
-    priority: Option<u8>,
-    num_times_rewatched: Option<u32>,
-    rewatch_value: Option<u8>,
-    tags: Option<Vec<String>>,
-    comments: Option<String>,
-}
-
-impl StatusUpdate {
-    pub fn new() -> Self {
-        Self::default()
-    }
-
-    pub fn status(&mut self, status: Status) {
-        self.status = Some(status);
+impl From<&str> for WatchStatus {
+    fn from(s: &str) -> Self {
+        match s {
+            "watching" => Self::Watching,
+            "completed" => Self::Completed,
+            "on_hold" => Self::OnHold,
+            "dropped" => Self::Dropped,
+            "plan_to_watch" => Self::PlanToWatch,
+            other => Self::Other(other.to_owned()),
+        }
     }
+}
 
-    pub fn is_rewatching(&mut self, is_rewatching: bool) {
-        self.is_rewatching = Some(is_rewatching);
+impl Serialize for WatchStatus {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
+}
 
-    pub fn score(&mut self, score: u8) {
-        self.score = Some(score);
-    }
-    pub fn num_watched_episodes(&mut self, num_watched_episodes: u32) {
-        self.num_watched_episodes = Some(num_watched_episodes);
-    }
-    pub fn priority(&mut self, priority: u8) {
-        self.priority = Some(priority);
-    }
-    pub fn num_times_rewatched(&mut self, num_times_rewatched: u32) {
-        self.num_times_rewatched = Some(num_times_rewatched);
-    }
-    pub fn rewatch_value(&mut self, rewatch_value: u8) {
-        self.rewatch_value = Some(rewatch_value);
-    }
-    pub fn tags(&mut self, tags: Vec<String>) {
-        self.tags = Some(tags);
-    }
-    pub fn comments(&mut self, comments: &str) {
-        self.comments = Some(comments.to_owned());
+impl<'de> Deserialize<'de> for WatchStatus {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Ok(String::deserialize(deserializer)?.as_str().into())
     }
 }
 
-impl Params for StatusUpdate {
-    fn get_params<'a>(self) -> Vec<(&'a str, String)> {
-        let mut params = vec![];
-        if let Some(s) = self.status {
-            params.push(("status", s.to_string()));
-        }
-        if let Some(rw) = self.is_rewatching {
-            params.push(("is_rewatching", rw.to_string()));
-        }
-        if let Some(t) = self.score {
-            params.push(("score", t.to_string()));
-        }
-        if let Some(t) = self.num_watched_episodes {
-            params.push(("num_watched_episodes", t.to_string()));
-        }
-        if let Some(t) = self.priority {
-            params.push(("priority", t.to_string()));
-        }
-        if let Some(t) = self.num_times_rewatched {
-            params.push(("num_times_rewatched", t.to_string()));
-        }
-        if let Some(t) = self.rewatch_value {
-            params.push(("rewatch_value", t.to_string()));
-        }
-        if let Some(t) = self.tags {
-            params.push(("tags", t.join(",")));
-        }
-        if let Some(t) = self.comments {
-            params.push(("comments", t));
-        }
-
-        params
-    }
+pub trait Params {
+    fn get_params<'a>(self) -> Vec<(&'a str, String)>;
 }
 
-pub struct StatusBuilder {
-    status: Option<Status>,
+///An update to an entry in the user's anime list. Only the fields that have been set are sent
+///to MAL, everything else is left unchanged.
+///
+///# Example
+///
+///```
+/// use lib_mal::model::options::{StatusUpdate, WatchStatus};
+///
+/// let update = StatusUpdate::new().status(WatchStatus::Watching).num_watched_episodes(3).score(8);
+///```
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct StatusUpdate {
+    status: Option<WatchStatus>,
     is_rewatching: Option<bool>,
     score: Option<u8>,
     num_watched_episodes: Option<u32>,
@@ -170,30 +132,20 @@ pub struct StatusBuilder {
     rewatch_value: Option<u8>,
     tags: Option<Vec<String>>,
     comments: Option<String>,
+    start_date: Option<String>,
+    finish_date: Option<String>,
 }
 
-impl Default for StatusBuilder {
-    fn default() -> Self {
-        Self::new()
-    }
-}
+///`StatusUpdate` can be built directly now, this is only kept around for compatibility
+#[deprecated(since = "0.6.0", note = "use `StatusUpdate` directly")]
+pub type StatusBuilder = StatusUpdate;
 
-impl StatusBuilder {
+impl StatusUpdate {
     pub fn new() -> Self {
-        StatusBuilder {
-            status: None,
-            is_rewatching: None,
-            score: None,
-            num_watched_episodes: None,
-            priority: None,
-            num_times_rewatched: None,
-            rewatch_value: None,
-            tags: None,
-            comments: None,
-        }
+        Self::default()
     }
 
-    pub fn status(mut self, status: impl Into<Option<Status>>) -> Self {
+    pub fn status(mut self, status: impl Into<Option<WatchStatus>>) -> Self {
         self.status = status.into();
         self
     }
@@ -228,6 +180,10 @@ impl StatusBuilder {
         self
     }
 
+    ///Sets the tags for the list entry, replacing any existing ones. MAL joins tags with commas
+    ///and has no way to escape one, so tags containing a comma are rejected with an
+    ///`invalid_tags` error when the update is sent. Other characters, including unicode, are
+    ///fine.
     pub fn tags(mut self, tags: impl Into<Option<Vec<String>>>) -> Self {
         self.tags = tags.into();
         self
@@ -238,17 +194,129 @@ impl StatusBuilder {
         self
     }
 
-    pub fn build(self) -> StatusUpdate {
-        StatusUpdate {
-            status: self.status,
-            is_rewatching: self.is_rewatching,
-            score: self.score,
-            num_watched_episodes: self.num_watched_episodes,
-            priority: self.priority,
-            num_times_rewatched: self.num_times_rewatched,
-            rewatch_value: self.rewatch_value,
-            tags: self.tags,
-            comments: self.comments,
+    ///Sets the date the user started watching
+    pub fn start_date(mut self, start_date: impl ListDate) -> Self {
+        self.start_date = Some(start_date.to_list_date());
+        self
+    }
+
+    ///Removes the start date from the list entry
+    pub fn clear_start_date(mut self) -> Self {
+        self.start_date = Some(String::new());
+        self
+    }
+
+    ///Sets the date the user finished watching
+    pub fn finish_date(mut self, finish_date: impl ListDate) -> Self {
+        self.finish_date = Some(finish_date.to_list_date());
+        self
+    }
+
+    ///Removes the finish date from the list entry
+    pub fn clear_finish_date(mut self) -> Self {
+        self.finish_date = Some(String::new());
+        self
+    }
+
+    ///Checks that the values set on the update are in the ranges MAL accepts. `score` has to be
+    ///between 0 and 10, `priority` between 0 and 2, and `rewatch_value` between 0 and 5.
+    ///Tags can't contain commas since MAL uses them to separate tags.
+    ///
+    ///This is done automatically by `MALClient::update_user_anime_status`
+    pub fn validate(&self) -> Result<(), MALError> {
+        check_range("score", self.score, 10)?;
+        check_range("priority", self.priority, 2)?;
+        check_range("rewatch_value", self.rewatch_value, 5)?;
+        if let Some(tag) = self.tags.iter().flatten().find(|t| t.contains(',')) {
+            return Err(MALError::new(
+                "Tags can't contain commas",
+                "invalid_tags",
+                tag.clone(),
+            ));
         }
+        Ok(())
+    }
+
+    #[deprecated(since = "0.6.0", note = "`StatusUpdate` no longer needs to be built")]
+    pub fn build(self) -> Self {
+        self
+    }
+}
+
+///A date that can be set on a list entry. Strings are expected to already be formatted as
+///`YYYY-MM-DD`, with the `chrono` feature enabled `chrono::NaiveDate` and `PartialDate` can be used
+///as well.
+pub trait ListDate {
+    fn to_list_date(&self) -> String;
+}
+
+impl ListDate for &str {
+    fn to_list_date(&self) -> String {
+        (*self).to_owned()
+    }
+}
+
+impl ListDate for String {
+    fn to_list_date(&self) -> String {
+        self.clone()
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl ListDate for chrono::NaiveDate {
+    fn to_list_date(&self) -> String {
+        self.format("%Y-%m-%d").to_string()
+    }
+}
+
+fn check_range(name: &str, value: Option<u8>, max: u8) -> Result<(), MALError> {
+    match value {
+        Some(v) if v > max => Err(MALError::new(
+            &format!("{} must be between 0 and {}", name, max),
+            &format!("invalid_{}", name),
+            v.to_string(),
+        )),
+        _ => Ok(()),
+    }
+}
+
+impl Params for StatusUpdate {
+    fn get_params<'a>(self) -> Vec<(&'a str, String)> {
+        let mut params = vec![];
+        if let Some(s) = self.status {
+            params.push(("status", s.to_string()));
+        }
+        if let Some(rw) = self.is_rewatching {
+            params.push(("is_rewatching", rw.to_string()));
+        }
+        if let Some(t) = self.score {
+            params.push(("score", t.to_string()));
+        }
+        if let Some(t) = self.num_watched_episodes {
+            params.push(("num_watched_episodes", t.to_string()));
+        }
+        if let Some(t) = self.priority {
+            params.push(("priority", t.to_string()));
+        }
+        if let Some(t) = self.num_times_rewatched {
+            params.push(("num_times_rewatched", t.to_string()));
+        }
+        if let Some(t) = self.rewatch_value {
+            params.push(("rewatch_value", t.to_string()));
+        }
+        if let Some(t) = self.tags {
+            params.push(("tags", t.join(",")));
+        }
+        if let Some(t) = self.comments {
+            params.push(("comments", t));
+        }
+        if let Some(t) = self.start_date {
+            params.push(("start_date", t));
+        }
+        if let Some(t) = self.finish_date {
+            params.push(("finish_date", t));
+        }
+
+        params
     }
 }
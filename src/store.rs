@@ -0,0 +1,567 @@
+//! Where the client keeps its tokens between runs
+
+use async_trait::async_trait;
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use zeroize::Zeroizing;
+
+use aes_gcm::aead::{consts::U32, Aead, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use hkdf::Hkdf;
+use rand::{rngs::OsRng, RngCore};
+use sha2::Sha256;
+use simple_log::{info, warn};
+
+use crate::{MALError, Tokens};
+
+///Persists a client's tokens so the user doesn't have to log in every time. The client saves the
+///tokens whenever they change and loads them in `ClientBuilder::build_with_refresh`.
+///
+///# Example
+///
+///```
+/// use async_trait::async_trait;
+/// use lib_mal::{store::TokenStore, MALError, Tokens};
+/// use std::sync::Mutex;
+///
+/// #[derive(Default)]
+/// struct MemoryStore(Mutex<Option<Tokens>>);
+///
+/// #[async_trait]
+/// impl TokenStore for MemoryStore {
+///     async fn load(&self) -> Result<Option<Tokens>, MALError> {
+///         Ok(self.0.lock().unwrap().clone())
+///     }
+///
+///     async fn save(&self, tokens: &Tokens) -> Result<(), MALError> {
+///         *self.0.lock().unwrap() = Some(tokens.clone());
+///         Ok(())
+///     }
+///
+///     async fn clear(&self) -> Result<(), MALError> {
+///         *self.0.lock().unwrap() = None;
+///         Ok(())
+///     }
+/// }
+///```
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    ///Returns the stored tokens, or `None` if nothing has been stored yet
+    async fn load(&self) -> Result<Option<Tokens>, MALError>;
+
+    ///Stores `tokens`, replacing anything stored before
+    async fn save(&self, tokens: &Tokens) -> Result<(), MALError>;
+
+    ///Removes the stored tokens. Clearing an empty store isn't an error.
+    async fn clear(&self) -> Result<(), MALError>;
+}
+
+///Lets a store be shared between the client and the rest of the application
+#[async_trait]
+impl<T: TokenStore + ?Sized> TokenStore for Arc<T> {
+    async fn load(&self) -> Result<Option<Tokens>, MALError> {
+        (**self).load().await
+    }
+
+    async fn save(&self, tokens: &Tokens) -> Result<(), MALError> {
+        (**self).save(tokens).await
+    }
+
+    async fn clear(&self) -> Result<(), MALError> {
+        (**self).clear().await
+    }
+}
+
+///The profile the file stores use when none is set. Its tokens live directly in the cache dir,
+///where they were kept before profiles existed.
+pub const DEFAULT_PROFILE: &str = "default";
+
+///The default token store, which keeps the tokens encrypted in a file named `tokens` in a
+///directory. Like `PlainFileTokenStore` it uses `tokio::fs`, so it needs a tokio runtime.
+///
+///On Unix the file is created with mode 0o600 and missing directories with 0o700. On Windows they
+///inherit the permissions of the directory they're in.
+pub struct FileTokenStore {
+    dir: PathBuf,
+    secret: Option<Zeroizing<String>>,
+    profile: String,
+}
+
+impl FileTokenStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        FileTokenStore {
+            dir: dir.into(),
+            secret: None,
+            profile: DEFAULT_PROFILE.to_owned(),
+        }
+    }
+
+    ///Encrypts the tokens with a key derived from `secret`, normally the app's client secret, and
+    ///a random salt stored alongside them, so the file is useless without the secret. Files
+    ///written by `new` can still be read.
+    ///
+    ///`ClientBuilder` uses this automatically when a client secret is set.
+    pub fn with_secret(dir: impl Into<PathBuf>, secret: &str) -> Self {
+        FileTokenStore {
+            secret: Some(Zeroizing::new(secret.to_owned())),
+            ..FileTokenStore::new(dir)
+        }
+    }
+
+    ///Keeps the tokens of the named profile, so several users can share a cache dir. Profile
+    ///names may only contain letters, digits, `-`, `_` and `.`, anything else makes loading and
+    ///saving fail with an `invalid_profile` error.
+    ///
+    ///# Example
+    ///
+    ///```
+    /// use lib_mal::store::FileTokenStore;
+    /// let alice = FileTokenStore::new("/some/dir").profile("alice");
+    ///```
+    pub fn profile(mut self, name: impl Into<String>) -> Self {
+        self.profile = name.into();
+        self
+    }
+
+    fn secret(&self) -> Option<&str> {
+        self.secret.as_ref().map(|secret| secret.as_str())
+    }
+
+    fn profile_dir(&self) -> Result<PathBuf, MALError> {
+        profile_dir(&self.dir, &self.profile)
+    }
+}
+
+///Where the tokens of a profile live in a cache dir
+pub(crate) fn profile_dir(dir: &Path, name: &str) -> Result<PathBuf, MALError> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.'));
+    if !valid {
+        return Err(MALError::new(
+            &format!("{:?} is not a valid profile name", name),
+            "invalid_profile",
+            None,
+        ));
+    }
+    Ok(if name == DEFAULT_PROFILE {
+        dir.to_path_buf()
+    } else {
+        dir.join("profiles").join(name)
+    })
+}
+
+///The profiles with tokens saved in a cache dir, sorted by name
+pub(crate) async fn list_profiles(dir: &Path) -> Result<Vec<String>, MALError> {
+    let unreadable = |e: io::Error| MALError::io("Unable to read token profiles", e);
+    let mut profiles = vec![];
+    if fs::metadata(dir.join("tokens")).await.is_ok() {
+        profiles.push(DEFAULT_PROFILE.to_owned());
+    }
+    let mut entries = match fs::read_dir(dir.join("profiles")).await {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(profiles),
+        Err(e) => return Err(unreadable(e)),
+    };
+    while let Some(entry) = entries.next_entry().await.map_err(unreadable)? {
+        if fs::metadata(entry.path().join("tokens")).await.is_ok() {
+            profiles.extend(entry.file_name().into_string());
+        }
+    }
+    profiles.sort();
+    Ok(profiles)
+}
+
+#[async_trait]
+impl TokenStore for FileTokenStore {
+    async fn load(&self) -> Result<Option<Tokens>, MALError> {
+        let raw = match read_cache(&self.profile_dir()?).await? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        let (tokens, version) = decode_cache(&raw, self.secret())?;
+        if version < CACHE_VERSION {
+            //not being able to upgrade now just means trying again next time
+            match self.save(&tokens).await {
+                Ok(()) => info!("Upgraded token cache from version {}", version),
+                Err(e) => warn!("Unable to upgrade token cache: {}", e),
+            }
+        }
+        Ok(Some(tokens))
+    }
+
+    async fn save(&self, tokens: &Tokens) -> Result<(), MALError> {
+        write_cache(&self.profile_dir()?, &encode_cache(tokens, self.secret())).await
+    }
+
+    async fn clear(&self) -> Result<(), MALError> {
+        let dir = self.profile_dir()?;
+        if self.profile != DEFAULT_PROFILE {
+            //the profile's directory only holds its tokens
+            return match fs::remove_dir_all(dir).await {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    Err(MALError::io("Unable to delete token cache", e))
+                }
+                _ => Ok(()),
+            };
+        }
+        match fs::remove_file(dir.join("tokens")).await {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                Err(MALError::io("Unable to delete token cache", e))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+///Reads the `tokens` file in a directory, if there is one
+async fn read_cache(dir: &Path) -> Result<Option<Vec<u8>>, MALError> {
+    match fs::read(dir.join("tokens")).await {
+        Ok(raw) => Ok(Some(raw)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(MALError::io("Unable to read token cache", e)),
+    }
+}
+
+///Writes the `tokens` file, creating the directory first if it doesn't exist yet.
+///
+///On Unix the file is only readable by the user (0o600) and directories created for it are
+///0o700, existing directories are left alone. On Windows both inherit the permissions of the
+///directory they're in, which for the usual places under the user's profile means only that user
+///can read them.
+async fn write_cache(dir: &Path, contents: &[u8]) -> Result<(), MALError> {
+    async fn write(dir: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut builder = fs::DirBuilder::new();
+        builder.recursive(true);
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create(true).truncate(true);
+        #[cfg(unix)]
+        {
+            builder.mode(0o700);
+            options.mode(0o600);
+        }
+        builder.create(dir).await?;
+        let mut file = options.open(dir.join("tokens")).await?;
+        //the mode only applies to new files
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            file.set_permissions(std::fs::Permissions::from_mode(0o600))
+                .await?;
+        }
+        file.write_all(contents).await?;
+        file.flush().await
+    }
+    write(dir, contents)
+        .await
+        .map_err(|e| MALError::io("Unable to write token cache", e))
+}
+
+///Keeps the tokens as plain JSON in a file named `tokens` in a directory, for setups where the
+///disk is already encrypted and the tokens should be easy to inspect or move. Only use this if you
+///mean to, the client never picks it by itself.
+///
+///Loading also accepts a file written by `FileTokenStore`, so switching over keeps the user
+///logged in. The file looks like this and will stay that way:
+///
+///```json
+///{
+///  "access_token": "...",
+///  "refresh_token": "...",
+///  "expires_in": 2678400,
+///  "today": 1650000000
+///}
+///```
+///
+///`today` is the unix time in seconds when the tokens were issued and `expires_in` is how many
+///seconds after that the access token expires.
+pub struct PlainFileTokenStore {
+    dir: PathBuf,
+}
+
+impl PlainFileTokenStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        PlainFileTokenStore { dir: dir.into() }
+    }
+}
+
+#[async_trait]
+impl TokenStore for PlainFileTokenStore {
+    async fn load(&self) -> Result<Option<Tokens>, MALError> {
+        let raw = match read_cache(&self.dir).await? {
+            Some(raw) => raw,
+            None => return Ok(None),
+        };
+        match serde_json::from_slice(&raw) {
+            Ok(tokens) => Ok(Some(tokens)),
+            //left behind by FileTokenStore
+            Err(_) => decode_cache(&raw, None).map(|(tokens, _)| Some(tokens)),
+        }
+    }
+
+    async fn save(&self, tokens: &Tokens) -> Result<(), MALError> {
+        let json = serde_json::to_vec_pretty(tokens).unwrap_or_default();
+        write_cache(&self.dir, &json).await
+    }
+
+    async fn clear(&self) -> Result<(), MALError> {
+        FileTokenStore::new(self.dir.clone()).clear().await
+    }
+}
+
+///Keeps the tokens in the platform's credential manager, like the macOS Keychain, the Secret
+///Service on Linux or the Windows Credential Manager. Entries are keyed by the client_id and a
+///profile name, so several users of the same app can each have their own.
+///
+///Requires the `keyring` feature and a tokio runtime
+///
+///# Example
+///
+///```no_run
+/// # use lib_mal::{ClientBuilder, MALError};
+/// use lib_mal::store::KeyringTokenStore;
+/// # async fn test() -> Result<(), MALError> {
+///     let client = ClientBuilder::new()
+///         .client_id("[YOUR_CLIENT_ID]".to_string())
+///         .token_store(Box::new(KeyringTokenStore::new("[YOUR_CLIENT_ID]", "default")))
+///         .build_with_refresh()
+///         .await?;
+///     # Ok(())
+/// # }
+///```
+#[cfg(feature = "keyring")]
+pub struct KeyringTokenStore {
+    service: String,
+    profile: String,
+}
+
+#[cfg(feature = "keyring")]
+impl KeyringTokenStore {
+    pub fn new(client_id: &str, profile: &str) -> Self {
+        KeyringTokenStore {
+            service: format!("lib-mal:{}", client_id),
+            profile: profile.to_owned(),
+        }
+    }
+
+    ///Runs `f` on the keyring entry off of the async executor, since the platform APIs block
+    async fn with_entry<T: Send + 'static>(
+        &self,
+        f: impl FnOnce(keyring::Entry) -> keyring::Result<T> + Send + 'static,
+    ) -> Result<T, MALError> {
+        let (service, profile) = (self.service.clone(), self.profile.clone());
+        tokio::task::spawn_blocking(move || keyring::Entry::new(&service, &profile).and_then(f))
+            .await
+            .map_err(|e| {
+                MALError::new(
+                    "Unable to access the keyring",
+                    "keyring_error",
+                    e.to_string(),
+                )
+            })?
+            .map_err(|e| {
+                MALError::new(
+                    "Unable to access the keyring",
+                    "keyring_error",
+                    e.to_string(),
+                )
+            })
+    }
+}
+
+#[cfg(feature = "keyring")]
+#[async_trait]
+impl TokenStore for KeyringTokenStore {
+    async fn load(&self) -> Result<Option<Tokens>, MALError> {
+        let stored = self
+            .with_entry(|entry| match entry.get_password() {
+                Ok(json) => Ok(Some(json)),
+                Err(keyring::Error::NoEntry) => Ok(None),
+                Err(e) => Err(e),
+            })
+            .await?;
+        match stored {
+            Some(json) => serde_json::from_str(&json)
+                .map(Some)
+                .map_err(MALError::from),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, tokens: &Tokens) -> Result<(), MALError> {
+        let json = serde_json::to_string(tokens).unwrap_or_default();
+        self.with_entry(move |entry| entry.set_password(&json))
+            .await
+    }
+
+    async fn clear(&self) -> Result<(), MALError> {
+        self.with_entry(|entry| match entry.delete_credential() {
+            Err(keyring::Error::NoEntry) => Ok(()),
+            res => res,
+        })
+        .await
+    }
+}
+
+///Marks a token cache written in the versioned format, files without it are from version 1
+const CACHE_MAGIC: &[u8; 4] = b"lmal";
+///The cache format `FileTokenStore` writes
+///
+///- 1: no header, the tokens encrypted with the key and nonce built into the library
+///- 2: `CACHE_MAGIC`, the version, then either `SCHEME_BUILTIN` and a random nonce followed by the
+///  tokens encrypted with the built in key, or `SCHEME_SECRET` and the output of
+///  `encrypt_tokens_with_secret`
+pub(crate) const CACHE_VERSION: u8 = 2;
+const SCHEME_BUILTIN: u8 = 0;
+const SCHEME_SECRET: u8 = 1;
+const BUILTIN_KEY: [u8; 32] = *b"one two three four five six seve";
+
+///Encrypts the tokens in the current cache format
+pub(crate) fn encode_cache(toks: &Tokens, secret: Option<&str>) -> Vec<u8> {
+    let mut out = CACHE_MAGIC.to_vec();
+    out.push(CACHE_VERSION);
+    match secret {
+        Some(secret) => {
+            out.push(SCHEME_SECRET);
+            out.extend(encrypt_tokens_with_secret(toks, secret));
+        }
+        None => {
+            let mut nonce = [0u8; NONCE_LEN];
+            OsRng.fill_bytes(&mut nonce);
+            let cypher = Aes256Gcm::new(&Key::from(BUILTIN_KEY));
+            let plain = serde_json::to_vec(&toks).unwrap();
+            out.push(SCHEME_BUILTIN);
+            out.extend(nonce);
+            out.extend(cypher.encrypt(&Nonce::from(nonce), plain.as_ref()).unwrap());
+        }
+    }
+    out
+}
+
+///Decrypts a token cache in any known format, returning the tokens and the version it was in
+pub(crate) fn decode_cache(raw: &[u8], secret: Option<&str>) -> Result<(Tokens, u8), MALError> {
+    let header = CACHE_MAGIC.len();
+    if raw.len() > header + 1 && raw.starts_with(CACHE_MAGIC) {
+        let version = raw[header];
+        let body = &raw[header + 2..];
+        let res = match (version, raw[header + 1], secret) {
+            (CACHE_VERSION, SCHEME_BUILTIN, _) if body.len() >= NONCE_LEN => {
+                let (nonce, encrypted) = body.split_at(NONCE_LEN);
+                let mut nonce_bytes = [0u8; NONCE_LEN];
+                nonce_bytes.copy_from_slice(nonce);
+                let cypher = Aes256Gcm::new(&Key::from(BUILTIN_KEY));
+                match cypher.decrypt(&Nonce::from(nonce_bytes), encrypted) {
+                    Ok(plain) => serde_json::from_slice(&plain).map_err(MALError::from),
+                    Err(_) => Err(MALError::new(
+                        "Unable to decrypt encrypted tokens",
+                        "cache_unreadable",
+                        None,
+                    )),
+                }
+            }
+            (CACHE_VERSION, SCHEME_SECRET, Some(secret)) => {
+                decrypt_tokens_with_secret(body, secret)
+            }
+            (CACHE_VERSION, SCHEME_SECRET, None) => Err(MALError::new(
+                "The token cache was encrypted with a client secret, but none is set",
+                "cache_unreadable",
+                None,
+            )),
+            (CACHE_VERSION, _, _) => Err(MALError::new(
+                "The token cache is corrupt",
+                "cache_unreadable",
+                None,
+            )),
+            //written by a newer version of the library, or garbage
+            _ => Err(MALError::new(
+                &format!("Unsupported token cache version {}", version),
+                "cache_unreadable",
+                None,
+            )),
+        };
+        //a version 1 file could start with the header by chance
+        return res
+            .map(|tokens| (tokens, version))
+            .or_else(|e| decrypt_tokens(raw).map(|tokens| (tokens, 1)).map_err(|_| e));
+    }
+    decrypt_tokens(raw).map(|tokens| (tokens, 1))
+}
+
+///Encrypts the tokens in the version 1 cache format
+#[cfg(test)]
+pub(crate) fn encrypt_token(toks: &Tokens) -> Vec<u8> {
+    let key = Key::from(BUILTIN_KEY);
+    let cypher = Aes256Gcm::new(&key);
+    let nonce = Nonce::from(*b"but the eart");
+    let plain = serde_json::to_vec(&toks).unwrap();
+    let res = cypher.encrypt(&nonce, plain.as_ref()).unwrap();
+    res
+}
+
+///Decrypts a version 1 token cache
+pub(crate) fn decrypt_tokens(raw: &[u8]) -> Result<Tokens, MALError> {
+    let key = Key::from(BUILTIN_KEY);
+    let cypher = Aes256Gcm::new(&key);
+    let nonce = Nonce::from(*b"but the eart");
+    match cypher.decrypt(&nonce, raw.as_ref()) {
+        Ok(plain) => serde_json::from_slice(&plain).map_err(MALError::from),
+        Err(_) => Err(MALError::new(
+            "Unable to decrypt encrypted tokens",
+            "cache_unreadable",
+            None,
+        )),
+    }
+}
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+///Derives the cache key from `secret` and `salt`
+fn derive_key(secret: &str, salt: &[u8]) -> Key<U32> {
+    let mut key = [0u8; 32];
+    //32 bytes is always a valid length for HKDF-SHA256
+    Hkdf::<Sha256>::new(Some(salt), secret.as_bytes())
+        .expand(b"lib-mal token cache", &mut key)
+        .unwrap();
+    Key::from(key)
+}
+
+///Encrypts the tokens with a key derived from `secret`, laid out as salt, nonce and ciphertext
+pub(crate) fn encrypt_tokens_with_secret(toks: &Tokens, secret: &str) -> Vec<u8> {
+    let mut salt = [0u8; SALT_LEN];
+    let mut nonce = [0u8; NONCE_LEN];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let cypher = Aes256Gcm::new(&derive_key(secret, &salt));
+    let plain = serde_json::to_vec(&toks).unwrap();
+    let encrypted = cypher.encrypt(&Nonce::from(nonce), plain.as_ref()).unwrap();
+    [&salt[..], &nonce[..], &encrypted[..]].concat()
+}
+
+pub(crate) fn decrypt_tokens_with_secret(raw: &[u8], secret: &str) -> Result<Tokens, MALError> {
+    let unreadable = || {
+        MALError::new(
+            "Unable to decrypt tokens, they were saved with a different secret or are corrupt",
+            "cache_unreadable",
+            None,
+        )
+    };
+    if raw.len() < SALT_LEN + NONCE_LEN {
+        return Err(unreadable());
+    }
+    let (salt, rest) = raw.split_at(SALT_LEN);
+    let (nonce, encrypted) = rest.split_at(NONCE_LEN);
+    let mut nonce_bytes = [0u8; NONCE_LEN];
+    nonce_bytes.copy_from_slice(nonce);
+    let cypher = Aes256Gcm::new(&derive_key(secret, salt));
+    let plain = cypher
+        .decrypt(&Nonce::from(nonce_bytes), encrypted)
+        .map_err(|_| unreadable())?;
+    Ok(serde_json::from_slice(&plain)?)
+}
@@ -1,9 +1,20 @@
 use std::env;
 
+use crate::client::Tokens;
+use crate::listener::parse_callback;
 use crate::model::fields::AnimeFields;
-use crate::model::options::{RankingType, Season};
-use crate::model::{AnimeDetails, AnimeList};
-use crate::MALClient;
+use crate::model::options::{Params, RankingType, Season, StatusUpdate, WatchStatus};
+use crate::model::{AnimeDetails, AnimeList, ListStatus, User};
+use crate::store::{
+    decode_cache, decrypt_tokens, encrypt_token, TokenStore, CACHE_VERSION, DEFAULT_PROFILE,
+};
+use crate::{ClientBuilder, MALClient, MALError};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::PathBuf;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, SystemTime};
+use tiny_http::{Response, Server};
 use tokio_test::block_on;
 
 #[test]
@@ -11,7 +22,8 @@ fn anime_list() {
     let client = setup();
     let expected =
         serde_json::from_str::<AnimeList>(include_str!("test-data/anime_list.json")).unwrap();
-    let result = block_on(client.get_anime_list("one", Some(4))).expect("Error performing request");
+    let result =
+        block_on(client.get_anime_list("one", Some(4), None)).expect("Error performing request");
     let first = expected.data[0].node.id;
     let res_first = result.data[0].node.id;
     assert_eq!(first, res_first); //Really don't want to implement partial_eq for all these structs lol
@@ -34,7 +46,7 @@ fn anime_ranking() {
     let client = setup();
     let expected =
         serde_json::from_str::<AnimeList>(include_str!("test-data/anime_ranking.json")).unwrap();
-    let result = block_on(client.get_anime_ranking(RankingType::All, Some(4)))
+    let result = block_on(client.get_anime_ranking(RankingType::All, Some(4), None))
         .expect("Error performing request");
     let left = expected.data[0].node.id;
     let right = result.data[0].node.id;
@@ -46,14 +58,3219 @@ fn seasonal_anime() {
     let client = setup();
     // let expected =
     //     serde_json::from_str::<AnimeList>(include_str!("test-data/seasonal_anime.json")).unwrap();
-    let result = block_on(client.get_seasonal_anime(Season::Summer, 2017, Some(4)));
+    let result = block_on(client.get_seasonal_anime(Season::Summer, 2017, Some(4), None));
     //.expect("Error performing request");
     // let left = expected.data[0].node.id;
     // let right = result.data[0].node.id;
     assert!(result.is_ok());
 }
 
+#[test]
+fn status_update_params() {
+    let params = StatusUpdate::new()
+        .status(WatchStatus::Completed)
+        .score(9)
+        .get_params();
+    assert_eq!(
+        params,
+        vec![
+            ("status", "completed".to_owned()),
+            ("score", "9".to_owned())
+        ]
+    );
+    assert!(StatusUpdate::new().get_params().is_empty());
+}
+
+#[test]
+fn watch_status_serde() {
+    let status: ListStatus =
+        serde_json::from_str(r#"{"status": "plan_to_watch", "score": 0}"#).unwrap();
+    assert_eq!(status.status, Some(WatchStatus::PlanToWatch));
+
+    let status: ListStatus = serde_json::from_str(r#"{"status": "rewatching"}"#).unwrap();
+    assert_eq!(
+        status.status,
+        Some(WatchStatus::Other("rewatching".to_owned()))
+    );
+
+    let json = serde_json::to_string(&WatchStatus::OnHold).unwrap();
+    assert_eq!(json, r#""on_hold""#);
+}
+
+#[test]
+fn status_update_validation() {
+    for score in 0..=10 {
+        assert!(StatusUpdate::new().score(score).validate().is_ok());
+    }
+    for priority in 0..=2 {
+        assert!(StatusUpdate::new().priority(priority).validate().is_ok());
+    }
+    for rewatch_value in 0..=5 {
+        assert!(StatusUpdate::new()
+            .rewatch_value(rewatch_value)
+            .validate()
+            .is_ok());
+    }
+
+    let err = StatusUpdate::new().score(11).validate().unwrap_err();
+    assert_eq!(err.error(), "invalid_score");
+    let err = StatusUpdate::new().priority(3).validate().unwrap_err();
+    assert_eq!(err.error(), "invalid_priority");
+    let err = StatusUpdate::new().rewatch_value(6).validate().unwrap_err();
+    assert_eq!(err.error(), "invalid_rewatch_value");
+}
+
+#[test]
+fn invalid_update_not_sent() {
+    let client = MALClient::with_access_token("");
+    let res = block_on(client.update_user_anime_status(80, StatusUpdate::new().score(15)));
+    assert_eq!(res.unwrap_err().error(), "invalid_score");
+}
+
+#[test]
+fn status_update_dates() {
+    let params = StatusUpdate::new()
+        .start_date("2021-04-01")
+        .clear_finish_date()
+        .get_params();
+    assert_eq!(
+        params,
+        vec![
+            ("start_date", "2021-04-01".to_owned()),
+            ("finish_date", String::new())
+        ]
+    );
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn status_update_chrono_dates() {
+    let date = chrono::NaiveDate::from_ymd_opt(2021, 4, 1).unwrap();
+    let params = StatusUpdate::new().finish_date(date).get_params();
+    assert_eq!(params, vec![("finish_date", "2021-04-01".to_owned())]);
+}
+
+#[test]
+fn partial_dates() {
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    let status = details.my_list_status.as_ref().unwrap();
+    #[cfg(not(feature = "chrono"))]
+    {
+        assert_eq!(details.start_date.as_deref(), Some("1963"));
+        assert_eq!(details.end_date.as_deref(), Some("1966-12"));
+        assert_eq!(status.start_date.as_deref(), Some("2020-05"));
+    }
+    #[cfg(feature = "chrono")]
+    {
+        use crate::model::PartialDate;
+        use chrono::NaiveDate;
+        let year_only = details.start_date.unwrap();
+        assert_eq!(
+            year_only,
+            PartialDate {
+                year: 1963,
+                month: None,
+                day: None
+            }
+        );
+        assert_eq!(year_only.to_naive_date(), None);
+        let end = details.end_date.unwrap();
+        assert_eq!((end.year, end.month, end.day), (1966, Some(12), None));
+        assert_eq!(end.to_string(), "1966-12");
+        let finish = status.finish_date.unwrap();
+        assert_eq!(finish.to_naive_date(), NaiveDate::from_ymd_opt(2020, 6, 14));
+        assert_eq!(status.start_date.unwrap().to_string(), "2020-05");
+        assert_eq!(
+            details.created_at.unwrap().to_rfc3339(),
+            "2008-03-29T05:21:46+00:00"
+        );
+
+        let json = serde_json::to_string(&details.my_list_status).unwrap();
+        assert!(json.contains(r#""start_date":"2020-05""#), "{}", json);
+        for bad in ["07", "2007-13", "2007-1", "2007-02-30", "2007-10-01-01", ""] {
+            assert_eq!(
+                bad.parse::<PartialDate>().unwrap_err().error(),
+                "invalid_date",
+                "{}",
+                bad
+            );
+        }
+        let params = StatusUpdate::new().start_date(finish).get_params();
+        assert_eq!(params, vec![("start_date", "2020-06-14".to_owned())]);
+    }
+}
+
+#[test]
+fn status_update_tags_encoding() {
+    let update = StatusUpdate::new()
+        .tags(vec!["アニメ".to_owned(), "re watch".to_owned()])
+        .comments("Great & fun, 10/10".to_owned())
+        .priority(2)
+        .num_times_rewatched(1)
+        .rewatch_value(4);
+    assert!(update.validate().is_ok());
+    let req = reqwest::Client::new()
+        .patch("http://localhost")
+        .form(&update.get_params())
+        .build()
+        .unwrap();
+    let body = std::str::from_utf8(req.body().unwrap().as_bytes().unwrap()).unwrap();
+    assert_eq!(
+        body,
+        "priority=2&num_times_rewatched=1&rewatch_value=4\
+         &tags=%E3%82%A2%E3%83%8B%E3%83%A1%2Cre+watch&comments=Great+%26+fun%2C+10%2F10"
+    );
+
+    //MAL splits tags on commas and has no escape, so these are rejected before anything is sent
+    let err = StatusUpdate::new()
+        .tags(vec!["one, two".to_owned()])
+        .validate()
+        .unwrap_err();
+    assert_eq!(err.error(), "invalid_tags");
+    assert_eq!(err.message(), Some("Tags can't contain commas"));
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = format!("http://{}", free_addr());
+    let update = StatusUpdate::new().tags(vec!["アニメ".to_owned(), "one,two".to_owned()]);
+    let err = block_on(client.update_user_anime_status(80, update)).unwrap_err();
+    assert_eq!(err.error(), "invalid_tags");
+}
+
+#[test]
+fn list_status_extra_fields() {
+    let status: ListStatus = serde_json::from_str(
+        r#"{
+            "status": "completed",
+            "priority": 2,
+            "num_times_rewatched": 3,
+            "rewatch_value": 5,
+            "tags": ["アニメ", "favorites"],
+            "comments": "Watched with friends"
+        }"#,
+    )
+    .unwrap();
+    assert_eq!(status.priority, Some(2));
+    assert_eq!(status.num_times_rewatched, Some(3));
+    assert_eq!(status.rewatch_value, Some(5));
+    assert_eq!(
+        status.tags,
+        Some(vec!["アニメ".to_owned(), "favorites".to_owned()])
+    );
+    assert_eq!(status.comments.as_deref(), Some("Watched with friends"));
+}
+
 fn setup() -> MALClient {
     let token = env::var("MAL_TOKEN").expect("Access token not in environment");
     MALClient::with_access_token(&token)
 }
+
+#[test]
+fn update_uses_patch() {
+    let (url, server) = stub_server(vec![(200, r#"{"status": "watching", "score": 8}"#)]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let update = StatusUpdate::new().status(WatchStatus::Watching).score(8);
+    let status = block_on(client.update_user_anime_status(80, update)).unwrap();
+    assert_eq!(status.score, Some(8));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "PATCH");
+    assert_eq!(requests[0].url, "/anime/80/my_list_status");
+    assert_eq!(requests[0].body, "status=watching&score=8");
+}
+
+#[test]
+fn increment_adds_to_list() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "num_episodes": 43}"#,
+        ),
+        (200, r#"{"status": "watching", "num_episodes_watched": 1}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.increment_watched_episodes(80, true)).unwrap();
+    assert_eq!(status.num_episodes_watched, Some(1));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(
+        requests[0].url,
+        "/anime/80?fields=my_list_status,num_episodes"
+    );
+    assert_eq!(requests[1].method, "PATCH");
+    assert_eq!(requests[1].body, "status=watching&num_watched_episodes=1");
+}
+
+#[test]
+fn increment_counts_up() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "num_episodes": 43, "my_list_status": {"status": "watching", "num_episodes_watched": 12}}"#,
+        ),
+        (200, r#"{"status": "watching", "num_episodes_watched": 13}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.increment_watched_episodes(80, true)).unwrap();
+    assert_eq!(status.num_episodes_watched, Some(13));
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/80?fields=my_list_status,num_episodes"
+    );
+    assert_eq!(requests[1].url, "/anime/80/my_list_status");
+    assert_eq!(requests[1].body, "num_watched_episodes=13");
+}
+
+#[test]
+fn increment_completes_last_episode() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "num_episodes": 43, "my_list_status": {"status": "watching", "num_episodes_watched": 42}}"#,
+        ),
+        (
+            200,
+            r#"{"status": "completed", "num_episodes_watched": 43}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.increment_watched_episodes(80, true)).unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Completed));
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/80?fields=my_list_status,num_episodes"
+    );
+    assert_eq!(requests[1].body, "status=completed&num_watched_episodes=43");
+}
+
+#[test]
+fn errors_are_typed() {
+    let (url, _server) = stub_server(vec![
+        (
+            200,
+            r#"{"error": "invalid_parameters", "message": "fields is invalid"}"#,
+        ),
+        (200, "<html>not json</html>"),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    match block_on(client.get_anime_details(80, None)).unwrap_err() {
+        MALError::Api {
+            error,
+            message,
+            status,
+            ..
+        } => {
+            assert_eq!(error, "invalid_parameters");
+            assert_eq!(message.as_deref(), Some("fields is invalid"));
+            assert_eq!(status, Some(200));
+        }
+        e => panic!("expected an api error, got {:?}", e),
+    }
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(matches!(err, MALError::Parse { .. }));
+    assert_eq!(err.error(), "parse_error");
+    assert_eq!(err.info(), Some("<html>not json</html>"));
+
+    //the compatibility constructor still works
+    let err = MALError::new("Something broke", "broken", None);
+    assert_eq!(err.error(), "broken");
+    assert_eq!(err.message(), Some("Something broke"));
+}
+
+#[test]
+fn errors_carry_status() {
+    let (url, _server) = stub_server(vec![
+        (404, r#"{"error": "not_found"}"#),
+        (404, r#"{"error": "not_found"}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert_eq!(err.status(), Some(404));
+    let err = block_on(client.delete_anime_list_item(1)).unwrap_err();
+    assert_eq!(err.status(), Some(404));
+    assert_eq!(MALError::new("", "", None).status(), None);
+}
+
+#[test]
+fn non_success_responses_are_errors() {
+    let (url, _server) = stub_server(vec![
+        (
+            400,
+            r#"{"error": "invalid_parameters", "message": "limit is invalid"}"#,
+        ),
+        (401, r#"{"error": "invalid_token"}"#),
+        (404, r#"{"error": "not_found", "message": ""}"#),
+        (500, "Internal Server Error"),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+
+    let err = block_on(client.get_anime_list("one", 200, None)).unwrap_err();
+    assert!(matches!(err, MALError::Api { .. }), "{:?}", err);
+    assert_eq!(err.error(), "invalid_parameters");
+    assert_eq!(err.message(), Some("limit is invalid"));
+    assert_eq!(err.status(), Some(400));
+    assert_eq!(
+        err.info(),
+        Some(r#"{"error": "invalid_parameters", "message": "limit is invalid"}"#)
+    );
+
+    //without a refresh token there's nothing to retry with
+    let err = block_on(client.get_anime_list("one", None, None)).unwrap_err();
+    assert!(matches!(err, MALError::Auth { .. }), "{:?}", err);
+    assert_eq!(err.error(), "unauthorized");
+
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert_eq!(err.error(), "not_found");
+    assert_eq!(err.status(), Some(404));
+
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert!(
+        matches!(err, MALError::Http { status: 500, .. }),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.info(), Some("Internal Server Error"));
+}
+
+#[test]
+fn mal_error_bodies() {
+    let invalid_token = include_str!("test-data/errors/invalid_token.json");
+    let forbidden = include_str!("test-data/errors/forbidden.json");
+    let bad_request = include_str!("test-data/errors/bad_request.json");
+    let (url, _server) = stub_server(vec![
+        (401, invalid_token),
+        (403, forbidden),
+        (400, bad_request),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert!(matches!(err, MALError::Auth { .. }), "{:?}", err);
+    assert_eq!(err.error(), "unauthorized");
+    assert_eq!(
+        err.message(),
+        Some("Access token was rejected: invalid_token")
+    );
+
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert!(matches!(err, MALError::Api { .. }), "{:?}", err);
+    assert_eq!(err.error(), "forbidden");
+    assert_eq!(err.message(), None);
+    assert_eq!(err.status(), Some(403));
+    assert_eq!(err.info(), Some(forbidden));
+
+    let err = block_on(client.get_anime_list("x", None, None)).unwrap_err();
+    assert_eq!(err.error(), "bad_request");
+    assert_eq!(err.message(), Some("invalid q"));
+    assert_eq!(err.status(), Some(400));
+    assert_eq!(err.info(), Some(bad_request));
+}
+
+#[test]
+fn delete_failures_are_errors() {
+    let (url, server) = stub_server(vec![
+        (200, ""),
+        (403, r#"{"error": "forbidden"}"#),
+        (500, "Internal Server Error"),
+        (404, r#"{"error": "not_found", "message": ""}"#),
+        (200, ""),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    block_on(client.delete_anime_list_item(80)).unwrap();
+    let err = block_on(client.delete_anime_list_item(80)).unwrap_err();
+    assert_eq!(err.error(), "forbidden");
+    assert_eq!(err.status(), Some(403));
+    let err = block_on(client.delete_anime_list_item(80)).unwrap_err();
+    assert!(
+        matches!(err, MALError::Http { status: 500, .. }),
+        "{:?}",
+        err
+    );
+
+    assert!(!block_on(client.try_delete_anime_list_item(80)).unwrap());
+    assert!(block_on(client.try_delete_anime_list_item(80)).unwrap());
+    let requests = server.join().unwrap();
+    assert!(requests.iter().all(|req| req.method == "DELETE"));
+}
+
+#[test]
+fn anime_details_stubbed() {
+    let fixture = include_str!("test-data/anime_details.json");
+    let (url, _server) = stub_server(vec![(200, fixture)]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(30230, AnimeFields::ALL)).unwrap();
+    let expected = serde_json::from_str::<AnimeDetails>(fixture).unwrap();
+    assert_eq!(details, expected);
+
+    //list entries can be deduplicated
+    let statuses: std::collections::HashSet<_> =
+        vec![details.my_list_status, expected.my_list_status]
+            .into_iter()
+            .collect();
+    assert_eq!(statuses.len(), 1);
+}
+
+#[test]
+fn start_season() {
+    use crate::model::StartSeason;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(
+        details.start_season,
+        Some(StartSeason {
+            year: 2015,
+            season: Season::Spring
+        })
+    );
+    let seasonal: AnimeList =
+        serde_json::from_str(include_str!("test-data/seasonal_anime.json")).unwrap();
+    assert_eq!(seasonal.season.unwrap().season, Season::Summer);
+
+    for season in [Season::Winter, Season::Spring, Season::Summer, Season::Fall] {
+        let json = serde_json::to_string(&season).unwrap();
+        assert_eq!(json, format!("\"{}\"", season));
+        assert_eq!(serde_json::from_str::<Season>(&json).unwrap(), season);
+    }
+}
+
+#[test]
+fn broadcast() {
+    use crate::model::{Broadcast, DayOfWeek};
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let broadcast = details.broadcast.as_ref().unwrap();
+    assert_eq!(broadcast.day_of_the_week, DayOfWeek::Monday);
+    let other: Broadcast = serde_json::from_str(r#"{"day_of_the_week": "other"}"#).unwrap();
+    assert_eq!(other.day_of_the_week, DayOfWeek::Other);
+    assert_eq!(other.start_time, None);
+
+    #[cfg(not(feature = "chrono"))]
+    assert_eq!(broadcast.start_time.as_deref(), Some("18:00"));
+    #[cfg(feature = "chrono")]
+    {
+        use chrono::{DateTime, FixedOffset, Utc};
+        let at = |s: &str| s.parse::<DateTime<Utc>>().unwrap();
+        assert_eq!(
+            broadcast.start_time,
+            chrono::NaiveTime::from_hms_opt(18, 0, 0)
+        );
+        //17:00 on a Monday in Japan
+        assert_eq!(
+            details.next_broadcast(at("2022-03-07T08:00:00Z")),
+            Some(at("2022-03-07T09:00:00Z"))
+        );
+        assert_eq!(
+            details.next_broadcast(at("2022-03-07T09:00:00Z")),
+            Some(at("2022-03-07T09:00:00Z"))
+        );
+        assert_eq!(
+            details.next_broadcast(at("2022-03-07T10:00:00Z")),
+            Some(at("2022-03-14T09:00:00Z"))
+        );
+        //already Monday in Japan
+        assert_eq!(
+            details.next_broadcast(at("2022-03-06T20:00:00Z")),
+            Some(at("2022-03-07T09:00:00Z"))
+        );
+        //kept in the caller's time zone
+        let new_york = FixedOffset::west_opt(5 * 3600).unwrap();
+        let next = details
+            .next_broadcast(at("2022-03-07T08:00:00Z").with_timezone(&new_york))
+            .unwrap();
+        assert_eq!(next.offset(), &new_york);
+        assert_eq!(next.to_rfc3339(), "2022-03-07T04:00:00-05:00");
+
+        let no_time: Broadcast =
+            serde_json::from_str(r#"{"day_of_the_week": "friday", "start_time": null}"#).unwrap();
+        assert_eq!(no_time.next_after(&at("2022-03-07T08:00:00Z")), None);
+        assert_eq!(other.next_after(&at("2022-03-07T08:00:00Z")), None);
+        let json = serde_json::to_string(broadcast).unwrap();
+        assert_eq!(json, r#"{"day_of_the_week":"monday","start_time":"18:00"}"#);
+    }
+}
+
+#[test]
+fn alternative_titles() {
+    use crate::model::TitleLanguage;
+    let mut details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let titles = details.alternative_titles.as_ref().unwrap();
+    assert_eq!(titles.synonyms.len(), 2);
+    assert_eq!(titles.en.as_deref(), Some("Ace of Diamond: Second Season"));
+    assert_eq!(
+        details.title_in(TitleLanguage::English),
+        "Ace of Diamond: Second Season"
+    );
+    assert_eq!(
+        details.title_in(TitleLanguage::Japanese),
+        "ダイヤのA[エース]～Second Season～"
+    );
+    assert_eq!(
+        details.title_in(TitleLanguage::Romaji),
+        "Diamond no Ace: Second Season"
+    );
+
+    details.alternative_titles =
+        serde_json::from_str(r#"{"synonyms": [], "en": "", "ja": ""}"#).unwrap();
+    assert_eq!(
+        details.title_in(TitleLanguage::English),
+        "Diamond no Ace: Second Season"
+    );
+    details.alternative_titles = None;
+    assert_eq!(
+        details.title_in(TitleLanguage::Japanese),
+        "Diamond no Ace: Second Season"
+    );
+}
+
+#[test]
+fn genres() {
+    use crate::model::Genre;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let genres = details.genres.unwrap();
+    assert_eq!(
+        genres[0],
+        Genre {
+            id: 4,
+            name: "Comedy".to_owned()
+        }
+    );
+    let ids: Vec<u32> = genres.iter().map(|g| g.id).collect();
+    assert_eq!(ids, vec![4, 30, 23, 27]);
+
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(details.genres, None);
+}
+
+#[test]
+fn studios() {
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let studios: Vec<(u32, String)> = details
+        .studios
+        .unwrap()
+        .into_iter()
+        .map(|s| (s.id, s.name))
+        .collect();
+    assert_eq!(
+        studios,
+        vec![
+            (10, "Production I.G".to_owned()),
+            (11, "Madhouse".to_owned())
+        ]
+    );
+
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(details.studios, None);
+}
+
+#[test]
+fn related_anime() {
+    use crate::model::RelationType;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let related = details.related_anime.unwrap();
+    assert_eq!(related[0].node.id, 18689);
+    assert_eq!(related[0].relation_type, RelationType::Prequel);
+    assert_eq!(related[1].relation_type, RelationType::SideStory);
+
+    for name in ["sequel", "alternative_version", "spin_off", "crossover"] {
+        let relation: RelationType = serde_json::from_value(name.into()).unwrap();
+        assert_eq!(relation.to_string(), name);
+    }
+    assert_eq!(
+        RelationType::from("crossover"),
+        RelationType::Other("crossover".to_owned())
+    );
+}
+
+#[test]
+fn recommendations() {
+    let fixture = include_str!("test-data/anime_details.json");
+    let (url, server) = stub_server(vec![(200, fixture)]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(30230, AnimeFields::Recommendations)).unwrap();
+    let mut recommendations = details.recommendations.unwrap();
+    assert!(recommendations.len() >= 3);
+    recommendations.sort_by_key(|r| std::cmp::Reverse(r.num_recommendations));
+    assert!(recommendations
+        .windows(2)
+        .all(|w| w[0].num_recommendations >= w[1].num_recommendations));
+    assert!(!recommendations[0].node.title.is_empty());
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/30230?fields=recommendations");
+}
+
+#[test]
+fn pictures() {
+    use crate::model::Picture;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(
+        details.show.main_picture.as_ref().unwrap().large.as_deref(),
+        Some("https://api-cdn.myanimelist.net/images/anime/8/18576l.jpg")
+    );
+    let pictures = details.pictures.unwrap();
+    assert_eq!(Some(&pictures[0]), details.show.main_picture.as_ref());
+    assert_eq!(
+        pictures[1],
+        Picture {
+            medium: Some("https://api-cdn.myanimelist.net/images/anime/3/3021.jpg".to_owned()),
+            large: None
+        }
+    );
+
+    let anime: crate::model::Anime = serde_json::from_str(r#"{"id": 1, "title": "x"}"#).unwrap();
+    assert_eq!(anime.main_picture, None);
+}
+
+#[test]
+fn obscure_anime() {
+    let (url, _server) = stub_server(vec![
+        (200, include_str!("test-data/minimal_anime_details.json")),
+        (
+            200,
+            r#"{"data": [{"node": {"id": 54321, "title": "Kaeru no Uta"}}]}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(54321, None)).unwrap();
+    assert_eq!(details.show.main_picture, None);
+    assert_eq!(details.genres, None);
+    assert_eq!(details.studios, Some(vec![]));
+    assert_eq!(details.mean, None);
+    assert_eq!(details.num_episodes, None);
+    assert_eq!(
+        details.title_in(crate::model::TitleLanguage::English),
+        "Kaeru no Uta"
+    );
+    let related = details.related_anime.unwrap();
+    assert_eq!(related[0].node.main_picture, None);
+    assert_eq!(related[0].relation_type_formatted, "");
+    assert_eq!(details.statistics.unwrap().num_list_users, 0);
+
+    let list = block_on(client.get_anime_list("kaeru", 1, None)).unwrap();
+    assert_eq!(list.data[0].node.main_picture, None);
+    assert_eq!(list.next_page(), None);
+}
+
+#[cfg(feature = "raw-extras")]
+#[test]
+fn extra_fields_are_kept() {
+    //the fixtures are what MAL sends, so anything here is a field the models are missing
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let list: AnimeList = serde_json::from_str(include_str!("test-data/anime_list.json")).unwrap();
+    let user: User = serde_json::from_str(include_str!("test-data/user.json")).unwrap();
+    assert!(
+        details.show.extra.is_empty(),
+        "{:?}",
+        details.show.extra.keys()
+    );
+    assert!(list
+        .data
+        .iter()
+        .all(|n| n.extra.is_empty() && n.node.extra.is_empty()));
+    assert!(user.extra.is_empty(), "{:?}", user.extra.keys());
+
+    let details: AnimeDetails = serde_json::from_str(
+        r#"{"id": 80, "title": "Mobile Suit Gundam", "mean": 7.8, "trailer_url": "https://example.com"}"#,
+    )
+    .unwrap();
+    assert_eq!(details.mean, Some(7.8));
+    assert_eq!(details.show.extra["trailer_url"], "https://example.com");
+    assert_eq!(details.show.extra.len(), 1);
+    let json = serde_json::to_value(&details).unwrap();
+    assert_eq!(json["trailer_url"], "https://example.com");
+
+    let node: crate::model::ListNode = serde_json::from_str(
+        r#"{"node": {"id": 80, "title": "Mobile Suit Gundam", "mean": 7.8, "trailer_url": "https://example.com"}, "cursor": 3}"#,
+    )
+    .unwrap();
+    assert_eq!(node.node.mean, Some(7.8));
+    assert_eq!(node.node.extra.len(), 1);
+    assert_eq!(node.extra["cursor"], 3);
+}
+
+///Deserializes `json`, then checks that serializing it and deserializing it again gives the same
+///value back
+fn round_trip<T>(json: &str) -> T
+where
+    T: serde::de::DeserializeOwned + serde::Serialize + PartialEq + std::fmt::Debug,
+{
+    let value: T = serde_json::from_str(json).unwrap();
+    let again: T = serde_json::from_str(&serde_json::to_string(&value).unwrap()).unwrap();
+    assert_eq!(again, value);
+    value
+}
+
+#[test]
+fn fixtures_round_trip() {
+    use crate::model::{ForumBoards, ForumTopics, TopicDetails};
+    let full: AnimeDetails = round_trip(include_str!("test-data/anime_details.json"));
+    assert!(full.statistics.is_some() && full.broadcast.is_some());
+    let sparse: AnimeDetails = round_trip(include_str!("test-data/minimal_anime_details.json"));
+    assert_eq!(sparse.show.main_picture, None);
+    round_trip::<AnimeDetails>(include_str!("test-data/old_anime_details.json"));
+    let seasonal: AnimeList = round_trip(include_str!("test-data/seasonal_anime.json"));
+    assert!(seasonal.season.is_some());
+    let ranking: AnimeList = round_trip(include_str!("test-data/anime_ranking.json"));
+    assert!(ranking.data.iter().all(|n| n.ranking.is_some()));
+    round_trip::<AnimeList>(include_str!("test-data/anime_list.json"));
+    let list: AnimeList = round_trip(include_str!("test-data/user_anime_list.json"));
+    let status = list.data[0].list_status.as_ref().unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Completed));
+    assert_eq!(
+        status.tags.as_deref(),
+        Some(&["mecha".to_owned(), "classic".to_owned()][..])
+    );
+    assert_eq!(
+        list.next_page(),
+        Some("https://api.myanimelist.net/v2/users/@me/animelist?offset=3&fields=list_status&limit=3")
+    );
+    assert_eq!(list.paging.previous, None);
+    let user: User = round_trip(include_str!("test-data/user.json"));
+    assert!(user.anime_statistics.is_some());
+    round_trip::<ForumBoards>(include_str!("test-data/forum_boards.json"));
+    round_trip::<ForumTopics>(include_str!("test-data/forum_topics.json"));
+    round_trip::<TopicDetails>(include_str!("test-data/topic_with_poll.json"));
+    round_trip::<TopicDetails>(include_str!("test-data/topic_without_poll.json"));
+}
+
+#[test]
+fn search_fields() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"data": [
+                {"node": {"id": 80, "title": "Mobile Suit Gundam", "mean": 7.8, "num_episodes": 43, "media_type": "tv"}},
+                {"node": {"id": 81, "title": "Mobile Suit Gundam I", "media_type": "movie"}}
+            ], "paging": {}}"#,
+        ),
+        (200, include_str!("test-data/anime_list.json")),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let fields = AnimeFields::Mean | AnimeFields::NumEpisodes | AnimeFields::MediaType;
+    let list = block_on(client.get_anime_list("gundam", 2, fields)).unwrap();
+    let gundam = &list.data[0].node;
+    assert_eq!(gundam.id, 80);
+    assert_eq!(gundam.mean, Some(7.8));
+    assert_eq!(gundam.num_episodes, Some(43));
+    assert_eq!(gundam.media_type, Some(crate::model::MediaType::Tv));
+    let movie = &list.data[1].node;
+    assert_eq!((movie.mean, movie.num_episodes), (None, None));
+    assert_eq!(movie.media_type, Some(crate::model::MediaType::Movie));
+
+    let list = block_on(client.get_anime_list("one", 4, None)).unwrap();
+    assert!(list.data.iter().all(|n| n.node.mean.is_none()));
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime?q=gundam&limit=2&fields=mean,media_type,num_episodes"
+    );
+    assert_eq!(requests[1].url, "/anime?q=one&limit=4");
+}
+
+#[test]
+fn seasonal_fields() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"data": [
+            {"node": {"id": 36275, "title": "Natsume Yuujinchou Roku Specials", "mean": 8.2,
+                "genres": [{"id": 8, "name": "Drama"}], "studios": [{"id": 1, "name": "Shuka"}],
+                "broadcast": {"day_of_the_week": "wednesday", "start_time": "01:35"}}},
+            {"node": {"id": 34902, "title": "Tsurezure Children", "genres": []}}
+        ], "paging": {}, "season": {"year": 2017, "season": "summer"}}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let fields =
+        AnimeFields::Mean | AnimeFields::Genres | AnimeFields::Studios | AnimeFields::Broadcast;
+    let list = block_on(client.get_seasonal_anime(Season::Summer, 2017, None, fields)).unwrap();
+    let natsume = &list.data[0].node;
+    assert_eq!(natsume.mean, Some(8.2));
+    assert_eq!(natsume.genres.as_ref().unwrap()[0].name, "Drama");
+    assert_eq!(natsume.studios.as_ref().unwrap()[0].name, "Shuka");
+    assert!(natsume.broadcast.is_some());
+    let tsurezure = &list.data[1].node;
+    assert_eq!(tsurezure.genres, Some(vec![]));
+    assert_eq!(
+        (tsurezure.studios.as_ref(), tsurezure.broadcast.as_ref()),
+        (None, None)
+    );
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/season/2017/summer?limit=100&fields=mean,genres,broadcast,studios"
+    );
+}
+
+#[test]
+fn ranking_fields() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"data": [
+            {"node": {"id": 5114, "title": "Fullmetal Alchemist: Brotherhood", "mean": 9.1,
+                "num_list_users": 3300000, "media_type": "tv"}, "ranking": {"rank": 1}},
+            {"node": {"id": 28977, "title": "Gintama°", "media_type": "tv"},
+                "ranking": {"rank": 2, "previous_rank": 3}}
+        ], "paging": {}}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let fields = AnimeFields::Mean | AnimeFields::NumListUsers | AnimeFields::MediaType;
+    let list = block_on(client.get_anime_ranking(RankingType::All, 2, fields)).unwrap();
+    let top: Vec<_> = list
+        .data
+        .iter()
+        .map(|n| {
+            (
+                n.ranking.as_ref().unwrap().rank,
+                n.node.mean,
+                n.node.num_list_users,
+            )
+        })
+        .collect();
+    assert_eq!(top, [(1, Some(9.1), Some(3300000)), (2, None, None)]);
+    assert_eq!(
+        list.data[1].node.media_type,
+        Some(crate::model::MediaType::Tv)
+    );
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/ranking?ranking_type=all&limit=2&fields=mean,num_list_users,media_type"
+    );
+}
+
+#[test]
+fn suggestion_fields() {
+    let (url, server) = stub_server(vec![
+        (200, include_str!("test-data/suggested_anime.json")),
+        (200, r#"{"data": [], "paging": {}}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let fields = AnimeFields::Synopsis | AnimeFields::Genres;
+    let list = block_on(client.get_suggested_anime(2, fields)).unwrap();
+    let expected: AnimeList = round_trip(include_str!("test-data/suggested_anime.json"));
+    assert_eq!(list, expected);
+    let card = &list.data[0].node;
+    assert_eq!(card.title, "One Punch Man");
+    assert!(card.synopsis.as_ref().unwrap().starts_with("The seemingly"));
+    let genres: Vec<_> = card
+        .genres
+        .iter()
+        .flatten()
+        .map(|g| g.name.as_str())
+        .collect();
+    assert_eq!(genres, ["Action", "Comedy"]);
+    assert!(list.next_page().is_some());
+
+    //new users don't have any suggestions yet
+    let list = block_on(client.get_suggested_anime(None, None)).unwrap();
+    assert!(list.data.is_empty());
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/suggestions?limit=2&fields=synopsis,genres"
+    );
+    assert_eq!(requests[1].url, "/anime/suggestions?limit=100");
+}
+
+#[test]
+fn videos() {
+    let (url, server) = stub_server(vec![(200, include_str!("test-data/anime_details.json"))]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(30230, AnimeFields::Videos)).unwrap();
+    let videos = details.videos.unwrap();
+    assert_eq!(videos.len(), 2);
+    assert_eq!(videos[0].title, "PV 1");
+    assert_eq!(videos[0].url, "https://www.youtube.com/watch?v=7Nl4ibC7Hik");
+    assert_eq!(
+        videos[1].thumbnail.as_deref(),
+        Some("https://img.youtube.com/vi/Wb0HOg2t0lE/mqdefault.jpg")
+    );
+    #[cfg(feature = "chrono")]
+    assert_eq!(
+        videos[0].created_at,
+        Some("2015-03-10T23:32:36Z".parse().unwrap())
+    );
+    #[cfg(not(feature = "chrono"))]
+    assert_eq!(videos[0].created_at, Some(1426030356));
+    assert_eq!(videos[0].updated_at, videos[0].created_at);
+    assert!(AnimeFields::ALL.contains(AnimeFields::Videos));
+    assert!(AnimeFields::ALL.to_string().ends_with(",statistics,videos"));
+    assert_eq!(
+        "videos".parse::<AnimeFields>().unwrap(),
+        AnimeFields::Videos
+    );
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/30230?fields=videos");
+}
+
+#[test]
+fn statistics() {
+    use crate::model::{Statistics, StatusCounts};
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    let stats = details.statistics.unwrap();
+    assert_eq!(stats.num_list_users, 57194);
+    assert_eq!(
+        stats.status,
+        StatusCounts {
+            watching: 7799,
+            completed: 35492,
+            on_hold: 2802,
+            dropped: 1242,
+            plan_to_watch: 9859
+        }
+    );
+
+    let numbers: Statistics = serde_json::from_str(
+        r#"{"status": {"watching": 7799, "completed": "35492", "on_hold": 2802, "dropped": 1242, "plan_to_watch": 9859}, "num_list_users": "57194"}"#,
+    )
+    .unwrap();
+    assert_eq!(numbers, stats);
+    assert!(serde_json::from_str::<Statistics>(
+        r#"{"status": {"watching": "lots"}, "num_list_users": 1}"#
+    )
+    .is_err());
+}
+
+#[test]
+fn media_type() {
+    use crate::model::MediaType;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(details.media_type, Some(MediaType::Tv));
+    for (value, expected) in [
+        ("music", MediaType::Music),
+        ("ona", MediaType::Ona),
+        ("tv_special", MediaType::Other("tv_special".to_owned())),
+        ("cm", MediaType::Other("cm".to_owned())),
+    ] {
+        let parsed: MediaType = serde_json::from_value(value.into()).unwrap();
+        assert_eq!(parsed, expected);
+        assert_eq!(serde_json::to_value(&parsed).unwrap(), value);
+    }
+}
+
+#[test]
+fn airing_status() {
+    use crate::model::AiringStatus;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(details.status, Some(AiringStatus::FinishedAiring));
+    let airing: AiringStatus = serde_json::from_value("currently_airing".into()).unwrap();
+    assert_eq!(airing, AiringStatus::CurrentlyAiring);
+    assert_eq!(AiringStatus::NotYetAired.to_string(), "not_yet_aired");
+    assert_eq!(
+        AiringStatus::from("on_hiatus"),
+        AiringStatus::Other("on_hiatus".to_owned())
+    );
+}
+
+#[test]
+fn rating() {
+    use crate::model::Rating;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(details.rating, Some(Rating::Pg13));
+    let rating: Rating = serde_json::from_value("r+".into()).unwrap();
+    assert_eq!(rating, Rating::RPlus);
+    assert!(Rating::R < Rating::Rx);
+    assert!(Rating::G < Rating::Pg13 && Rating::Pg13 < Rating::R);
+    assert!(Rating::Other("nc_17".to_owned()) > Rating::Rx);
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(details.rating, None);
+}
+
+#[test]
+fn source() {
+    use crate::model::Source;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    let source = details.source.unwrap();
+    assert_eq!(source, Source::FourKomaManga);
+    assert_eq!(source.to_string(), "4-koma manga");
+    assert_eq!(serde_json::to_value(&source).unwrap(), "4_koma_manga");
+
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(details.source, Some(Source::Manga));
+    assert_eq!(Source::LightNovel.to_string(), "Light novel");
+    assert_eq!(Source::LightNovel.as_str(), "light_novel");
+    assert_eq!(Source::from("stage_play").to_string(), "stage_play");
+}
+
+#[test]
+fn nsfw() {
+    use crate::model::Nsfw;
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(details.nsfw, Some(Nsfw::White));
+    assert!(details.nsfw.unwrap().is_safe());
+    for value in ["gray", "black", "purple"] {
+        let nsfw: Nsfw = serde_json::from_value(value.into()).unwrap();
+        assert!(!nsfw.is_safe(), "{}", value);
+    }
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/old_anime_details.json")).unwrap();
+    assert_eq!(details.nsfw, None);
+}
+
+#[test]
+fn list_status_timestamps() {
+    let (url, _server) = stub_server(vec![(
+        200,
+        r#"{"status": "completed", "score": 8, "num_episodes_watched": 43, "is_rewatching": false,
+            "updated_at": "2023-02-11T20:41:07+00:00", "start_date": "2022-12-01",
+            "finish_date": "2023-02-11", "priority": 0, "num_times_rewatched": 0,
+            "rewatch_value": 0, "tags": [], "comments": ""}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(
+        client.update_user_anime_status(80, StatusUpdate::new().status(WatchStatus::Completed)),
+    )
+    .unwrap();
+    #[cfg(feature = "chrono")]
+    {
+        use chrono::{NaiveDate, TimeZone, Utc};
+        let watermark = Utc.with_ymd_and_hms(2023, 2, 1, 0, 0, 0).unwrap();
+        assert!(status.updated_at.unwrap() > watermark);
+        assert_eq!(
+            status.updated_at,
+            Some(Utc.with_ymd_and_hms(2023, 2, 11, 20, 41, 7).unwrap())
+        );
+        assert_eq!(
+            status.start_date.and_then(|d| d.to_naive_date()),
+            NaiveDate::from_ymd_opt(2022, 12, 1)
+        );
+        assert_eq!(
+            status.finish_date.and_then(|d| d.to_naive_date()),
+            NaiveDate::from_ymd_opt(2023, 2, 11)
+        );
+    }
+    #[cfg(not(feature = "chrono"))]
+    {
+        assert_eq!(
+            status.updated_at.as_deref(),
+            Some("2023-02-11T20:41:07+00:00")
+        );
+        assert_eq!(status.start_date.as_deref(), Some("2022-12-01"));
+        assert_eq!(status.finish_date.as_deref(), Some("2023-02-11"));
+    }
+}
+
+#[test]
+fn nested_list_status_fields() {
+    assert_eq!(AnimeFields::MyListStatus.to_string(), "my_list_status");
+    assert_eq!(
+        (AnimeFields::MyListStatus | AnimeFields::MyListStatusTags).to_string(),
+        "my_list_status{tags}"
+    );
+    //the sub-fields imply my_list_status, and keep their own order
+    assert_eq!(
+        (AnimeFields::Title
+            | AnimeFields::MyListStatusComments
+            | AnimeFields::MyListStatusNumTimesRewatched
+            | AnimeFields::NumEpisodes)
+            .to_string(),
+        "title,my_list_status{num_times_rewatched,comments},num_episodes"
+    );
+    assert_eq!(
+        (AnimeFields::ID | AnimeFields::MY_LIST_STATUS_ALL).to_string(),
+        "id,my_list_status{priority,num_times_rewatched,rewatch_value,tags,comments}"
+    );
+    assert!(!AnimeFields::ALL.to_string().contains('{'));
+
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"id": 80, "title": "Mobile Suit Gundam", "my_list_status": {"status": "completed", "tags": ["mecha"], "comments": "classic"}}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let fields = AnimeFields::MyListStatusTags | AnimeFields::MyListStatusComments;
+    let details = block_on(client.get_anime_details(80, fields)).unwrap();
+    let status = details.my_list_status.unwrap();
+    assert_eq!(status.tags, Some(vec!["mecha".to_owned()]));
+    assert_eq!(status.comments.as_deref(), Some("classic"));
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/anime/80?fields=my_list_status{tags,comments}"
+    );
+}
+
+#[test]
+fn fields_except() {
+    let all_names = AnimeFields::ALL.get_fields_names();
+    for field in AnimeFields::ALL.iter() {
+        let name = field.get_fields_names()[0];
+        let fields = AnimeFields::except(field);
+        assert!(!fields.contains(field), "{}", name);
+        assert_eq!(fields, AnimeFields::ALL - field);
+        let expected: Vec<_> = all_names.iter().filter(|n| **n != name).copied().collect();
+        assert_eq!(fields.to_string(), expected.join(","));
+    }
+
+    let fields = AnimeFields::except(AnimeFields::Synopsis | AnimeFields::Background);
+    assert_eq!(fields.get_fields_names().len(), all_names.len() - 2);
+    assert!((!AnimeFields::Synopsis).contains(AnimeFields::MyListStatusTags));
+    assert!(!AnimeFields::except(AnimeFields::Synopsis).contains(AnimeFields::MyListStatusTags));
+}
+
+#[test]
+fn fields_parse() {
+    for name in AnimeFields::ALL.iter_names() {
+        let field: AnimeFields = name.parse().unwrap();
+        assert_eq!(field.iter_names().collect::<Vec<_>>(), [name]);
+        assert_eq!(field.to_string().parse::<AnimeFields>().unwrap(), field);
+    }
+    for name in AnimeFields::MY_LIST_STATUS_ALL.get_list_status_fields_names() {
+        let field: AnimeFields = format!("my_list_status{{{}}}", name).parse().unwrap();
+        assert_eq!(field.get_list_status_fields_names(), [name]);
+        assert_eq!(field.to_string().parse::<AnimeFields>().unwrap(), field);
+    }
+    for fields in [
+        AnimeFields::ALL,
+        AnimeFields::empty(),
+        AnimeFields::MY_LIST_STATUS_ALL | AnimeFields::Rank,
+        AnimeFields::except(AnimeFields::Synopsis),
+    ] {
+        assert_eq!(fields.to_string().parse::<AnimeFields>().unwrap(), fields);
+    }
+
+    let fields: AnimeFields = "rank, mean,studios,my_list_status{tags, comments}"
+        .parse()
+        .unwrap();
+    assert_eq!(
+        fields,
+        AnimeFields::Rank
+            | AnimeFields::Mean
+            | AnimeFields::Studios
+            | AnimeFields::MyListStatusTags
+            | AnimeFields::MyListStatusComments
+    );
+    assert_eq!(
+        fields.iter_names().collect::<Vec<_>>(),
+        ["mean", "rank", "my_list_status", "studios"]
+    );
+
+    let err = "rank,score".parse::<AnimeFields>().unwrap_err();
+    assert_eq!(err.error(), "invalid_fields");
+    let message = err.message().unwrap();
+    assert!(message.contains("`score`") && message.contains("num_episodes"));
+    let err = "my_list_status{tags,notes}"
+        .parse::<AnimeFields>()
+        .unwrap_err();
+    assert!(err.message().unwrap().contains("`notes`"));
+    assert!("tags".parse::<AnimeFields>().is_err());
+}
+
+#[test]
+fn details_with_my_list_status() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}, "my_list_status": {"status": "watching", "num_episodes_watched": 12, "score": 8}}"#,
+        ),
+        (
+            200,
+            r#"{"id": 81, "title": "Not on the list", "main_picture": {}}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(80, AnimeFields::MyListStatus)).unwrap();
+    let status = details.my_list_status.unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Watching));
+    assert_eq!(status.num_episodes_watched, Some(12));
+    assert_eq!(block_on(client.get_my_anime_list_status(81)).unwrap(), None);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/80?fields=my_list_status");
+    assert_eq!(requests[1].url, "/anime/81?fields=my_list_status");
+}
+
+#[test]
+fn my_list_status_on_list() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}, "my_list_status": {"status": "completed", "num_episodes_watched": 43, "score": 9}}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.get_my_anime_list_status(80))
+        .unwrap()
+        .unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Completed));
+    assert_eq!(status.num_episodes_watched, Some(43));
+    assert_eq!(status.score, Some(9));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].url, "/anime/80?fields=my_list_status");
+}
+
+#[test]
+fn my_list_status_not_on_list() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"id": 81, "title": "Not on the list", "main_picture": {}}"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    assert_eq!(block_on(client.get_my_anime_list_status(81)).unwrap(), None);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/81?fields=my_list_status");
+}
+
+#[test]
+fn episode_counts() {
+    let details: AnimeDetails =
+        serde_json::from_str(include_str!("test-data/anime_details.json")).unwrap();
+    assert_eq!(details.num_episodes, Some(51));
+    assert_eq!(
+        details.average_episode_duration,
+        Some(Duration::from_secs(24 * 60))
+    );
+    let json = serde_json::to_value(&details).unwrap();
+    assert_eq!(json["average_episode_duration"], 1440);
+
+    let ongoing: AnimeDetails = serde_json::from_str(
+        r#"{"id": 21, "title": "One Piece", "main_picture": {}, "num_episodes": 0, "average_episode_duration": null}"#,
+    )
+    .unwrap();
+    assert_eq!(ongoing.num_episodes, None);
+    assert_eq!(ongoing.average_episode_duration, None);
+}
+
+#[test]
+fn user_profile() {
+    let (url, server) = stub_server(vec![(200, include_str!("test-data/user.json"))]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let user = block_on(client.get_my_user_info()).unwrap();
+    assert_eq!(user.name, "example_user");
+    assert_eq!(user.location.as_deref(), Some("Tokyo"));
+    assert_eq!(user.time_zone.as_deref(), Some("Asia/Tokyo"));
+    assert_eq!(user.is_supporter, Some(false));
+    let stats = user.anime_statistics.as_ref().unwrap();
+    assert_eq!(stats.num_items_completed, 214);
+    assert_eq!(stats.num_episodes, 4018);
+    assert!((stats.num_days_watched - 68.52).abs() < 1e-4);
+    assert!((stats.mean_score - 7.46).abs() < 1e-4);
+    #[cfg(feature = "chrono")]
+    assert_eq!(user.birthday.unwrap().to_string(), "1995-04-12");
+
+    let json = serde_json::to_string(&user).unwrap();
+    assert_eq!(serde_json::from_str::<User>(&json).unwrap(), user);
+
+    let bare: User = serde_json::from_str(
+        r#"{"id": 1, "name": "new_user", "joined_at": "2024-01-01T00:00:00+00:00"}"#,
+    )
+    .unwrap();
+    assert_eq!(bare.location, None);
+    assert!(bare.anime_statistics.is_none());
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].url,
+        "/users/@me?fields=anime_statistics,time_zone,is_supporter"
+    );
+}
+
+#[test]
+fn forum_boards() {
+    let (url, _server) = stub_server(vec![(200, include_str!("test-data/forum_boards.json"))]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let boards = block_on(client.get_forum_boards()).unwrap();
+    let titles: Vec<_> = boards.categories.iter().map(|c| c.title.as_str()).collect();
+    assert_eq!(titles, ["MyAnimeList", "Anime & Manga"]);
+    let guidelines = &boards.categories[0].boards[1];
+    assert_eq!(guidelines.id, 14);
+    assert_eq!(guidelines.title, "MAL Guidelines & FAQ");
+    assert_eq!(guidelines.subboards.len(), 3);
+    assert_eq!(guidelines.subboards[0].title, "Anime DB");
+    assert!(boards.categories[1].boards[0].subboards.is_empty());
+}
+
+#[test]
+fn forum_topics() {
+    let (url, server) = stub_server(vec![(200, include_str!("test-data/forum_topics.json"))]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let topics =
+        block_on(client.get_forum_topics(None, None, "gundam".to_owned(), None, None, 2)).unwrap();
+    assert_eq!(topics.data.len(), 2);
+    let topic = &topics.data[0];
+    assert_eq!(topic.id, 1946603);
+    assert_eq!(topic.created_by.name, "Stark700");
+    assert_eq!(topic.number_of_posts, 1412);
+    assert_eq!(
+        topic.last_post_created_by.as_ref().map(|u| u.id),
+        Some(15163044)
+    );
+    assert!(!topic.is_locked && topics.data[1].is_locked);
+    #[cfg(feature = "chrono")]
+    assert!(topics.data[0].last_post_created_at > topics.data[1].last_post_created_at);
+    assert_eq!(
+        topics.next_page(),
+        Some("https://api.myanimelist.net/v2/forum/topics?offset=2&q=gundam&limit=2")
+    );
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/forum/topics?q=gundam&limit=2");
+}
+
+#[test]
+fn topic_details() {
+    let (url, server) = stub_server(vec![
+        (200, include_str!("test-data/topic_with_poll.json")),
+        (200, include_str!("test-data/topic_without_poll.json")),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let topic = block_on(client.get_forum_topic_detail(1589534, 2)).unwrap();
+    assert_eq!(topic.title, "Best Gundam series?");
+    assert_eq!(topic.posts.len(), 2);
+    assert_eq!(topic.posts[1].number, 2);
+    assert_eq!(
+        topic.posts[0].created_by.name.as_deref(),
+        Some("example_user")
+    );
+    assert_eq!(topic.posts[0].signature, "[i]Sieg Zeon[/i]");
+    let poll = topic.poll.as_ref().unwrap();
+    assert!(!poll.close);
+    let votes: u32 = poll.options.iter().map(|o| o.votes).sum();
+    assert_eq!(votes, 82);
+    assert!(topic.next_page().is_some());
+    let json = serde_json::to_string(&topic).unwrap();
+    assert_eq!(
+        serde_json::from_str::<crate::model::TopicDetails>(&json).unwrap(),
+        topic
+    );
+
+    let topic = block_on(client.get_forum_topic_detail(73327, 2)).unwrap();
+    assert!(topic.poll.is_none());
+    assert_eq!(topic.posts[0].created_by.name, None);
+    assert_eq!(topic.next_page(), None);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/forum/topic/1589534?limit=2");
+}
+
+#[test]
+fn ids_are_typed() {
+    use crate::model::{AnimeId, BoardId, ForumBoards, SubboardId, TopicId};
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+        (200, r#"{"data": [], "paging": {}}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let details = block_on(client.get_anime_details(80, AnimeFields::Rank)).unwrap();
+    assert_eq!(details.show.id, AnimeId(80));
+    assert_eq!(details.show.id, 80);
+    assert_eq!(details.show.id.to_string(), "80");
+    assert_eq!(serde_json::to_value(details.show.id).unwrap(), 80);
+    block_on(client.get_forum_topics(BoardId(5), SubboardId::from(2), None, None, None, 1))
+        .unwrap();
+
+    let boards: ForumBoards =
+        serde_json::from_str(include_str!("test-data/forum_boards.json")).unwrap();
+    let guidelines = &boards.categories[0].boards[1];
+    assert_eq!(guidelines.id, BoardId(14));
+    assert_eq!(guidelines.subboards[0].id, SubboardId(2));
+    assert_eq!(u32::from(TopicId(7)), 7);
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/80?fields=rank");
+    assert_eq!(
+        requests[1].url,
+        "/forum/topics?board_id=5&subboard_id=2&limit=1"
+    );
+}
+
+#[test]
+fn anime_ranking_stubbed() {
+    let (url, _server) = stub_server(vec![
+        (200, include_str!("test-data/anime_ranking.json")),
+        (
+            200,
+            r#"{"data": [{"node": {"id": 1, "title": "Cowboy Bebop", "main_picture": {}}, "ranking": {"rank": 4, "previous_rank": 6}}], "paging": {}}"#,
+        ),
+        (200, r#"{"error": "invalid_token", "message": ""}"#),
+        (400, include_str!("test-data/errors/bad_request.json")),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+
+    let list = block_on(client.get_anime_ranking(RankingType::All, 4, None)).unwrap();
+    let expected: AnimeList =
+        serde_json::from_str(include_str!("test-data/anime_ranking.json")).unwrap();
+    assert_eq!(list, expected);
+    let ranks: Vec<Option<u32>> = list
+        .data
+        .iter()
+        .map(|node| node.ranking.as_ref().map(|r| r.rank))
+        .collect();
+    assert_eq!(ranks, vec![Some(1), Some(2), Some(3), Some(4)]);
+    //none of the fixture entries have a previous_rank
+    assert!(list
+        .data
+        .iter()
+        .all(|node| node.ranking.as_ref().unwrap().previous_rank.is_none()));
+    let ranking: crate::model::Ranking =
+        serde_json::from_str(r#"{"rank": 7, "previous_rank": null}"#).unwrap();
+    assert_eq!(ranking.previous_rank, None);
+
+    let list = block_on(client.get_anime_ranking(RankingType::All, 1, None)).unwrap();
+    let ranking = list.data[0].ranking.as_ref().unwrap();
+    assert_eq!((ranking.rank, ranking.previous_rank), (4, Some(6)));
+
+    let err = block_on(client.get_anime_ranking(RankingType::All, 4, None)).unwrap_err();
+    assert_eq!(err.error(), "invalid_token");
+    let err = block_on(client.get_anime_ranking(RankingType::All, 4, None)).unwrap_err();
+    assert_eq!(err.error(), "bad_request");
+}
+
+#[test]
+fn missing_entries_are_not_found() {
+    let (url, _server) = stub_server(vec![
+        (404, r#"{"error": "not_found", "message": ""}"#),
+        (404, "Not Found"),
+        (404, r#"{"error": "not_found", "message": ""}"#),
+        (500, "Internal Server Error"),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let err = block_on(client.get_anime_details(999999, None)).unwrap_err();
+    assert!(
+        matches!(
+            err,
+            MALError::NotFound {
+                resource: "anime",
+                id: 999999
+            }
+        ),
+        "{:?}",
+        err
+    );
+    let err = block_on(client.delete_anime_list_item(5)).unwrap_err();
+    assert!(matches!(
+        err,
+        MALError::NotFound {
+            resource: "anime",
+            id: 5
+        }
+    ));
+    let err = block_on(client.get_forum_topic_detail(7, None)).unwrap_err();
+    assert!(matches!(
+        err,
+        MALError::NotFound {
+            resource: "topic",
+            id: 7
+        }
+    ));
+    assert_eq!(err.to_string(), "No topic with id 7");
+
+    //anything else is left alone
+    let err = block_on(client.get_anime_details(1, None)).unwrap_err();
+    assert!(matches!(err, MALError::Http { status: 500, .. }));
+}
+
+#[test]
+fn rate_limits_have_retry_after() {
+    let (url, _server) = stub_server_with_headers(vec![
+        (429, vec![("Retry-After", "30")], ""),
+        (429, vec![], r#"{"error": "too_many_requests"}"#),
+        (429, vec![("Retry-After", "0")], ""),
+        (200, vec![], r#"{"status": "completed"}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(matches!(
+        err,
+        MALError::RateLimited {
+            retry_after: Some(d)
+        } if d == Duration::from_secs(30)
+    ));
+    assert_eq!(err.status(), Some(429));
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(matches!(err, MALError::RateLimited { retry_after: None }));
+    assert_eq!(err.error(), "rate_limited");
+
+    //bulk updates wait for as long as MAL asks instead of backing off
+    let start = std::time::Instant::now();
+    let update = (80, StatusUpdate::new().status(WatchStatus::Completed));
+    let results = block_on(client.update_user_anime_statuses(vec![update], 1));
+    assert!(results[0].1.is_ok());
+    assert!(start.elapsed() < Duration::from_millis(400));
+}
+
+#[test]
+fn error_sources() {
+    use std::error::Error;
+    fn assert_error<E: Error + Send + Sync + 'static>() {}
+    assert_error::<MALError>();
+
+    let mut client = MALClient::with_refresh_token("client_id", "refresh");
+    //nothing should be listening on port 1
+    client.auth_url = "http://127.0.0.1:1".to_owned();
+    let err: Box<dyn Error + Send + Sync> = Box::new(block_on(client.refresh()).unwrap_err());
+    assert!(err.source().unwrap().is::<reqwest::Error>());
+
+    let dir = temp_dir("error_source");
+    fs::write(dir.join("file"), "").unwrap();
+    let store = crate::store::FileTokenStore::new(dir.join("file"));
+    let tokens = Tokens::new("access".to_owned(), "refresh".to_owned(), SystemTime::now());
+    let err = block_on(store.save(&tokens)).unwrap_err();
+    assert!(err.source().unwrap().is::<std::io::Error>());
+
+    let (url, _server) = stub_server(vec![(200, "not json")]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(err.source().unwrap().is::<serde_json::Error>());
+}
+
+#[test]
+fn parse_errors_have_paths() {
+    let node = r#"{"node": {"id": 1, "title": "Cowboy Bebop", "main_picture": {}}}"#;
+    let mut nodes = vec![node; 20];
+    nodes[13] = r#"{"node": {"id": "2", "title": "Trigun", "main_picture": {}}}"#;
+    let bad_id: &'static str = Box::leak(
+        format!(r#"{{"data": [{}], "paging": {{}}}}"#, nodes.join(",\n")).into_boxed_str(),
+    );
+    let missing_title = r#"{"data": [{"node": {"id": 1, "main_picture": {}}}], "paging": {}}"#;
+    let (url, _server) = stub_server(vec![(200, bad_id), (200, missing_title)]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+
+    let err = block_on(client.get_anime_list("x", None, None)).unwrap_err();
+    match &err {
+        MALError::Parse { path, snippet, .. } => {
+            //the id is flattened into AnimeDetails, so serde only notices at the end of the node
+            assert_eq!(path, "data[13].node");
+            assert!(snippet.len() < 510, "{}", snippet);
+            assert!(snippet.contains("Trigun"), "{}", snippet);
+            assert!(snippet.starts_with("...") && snippet.ends_with("..."));
+        }
+        _ => panic!("{:?}", err),
+    }
+    assert_eq!(err.to_string(), "Unable to parse response: data[13].node");
+    let source = std::error::Error::source(&err).unwrap().to_string();
+    assert!(source.starts_with("invalid type"), "{}", source);
+
+    let err = block_on(client.get_anime_list("x", None, None)).unwrap_err();
+    assert!(
+        matches!(&err, MALError::Parse { path, .. } if path == "data[0].node"),
+        "{:?}",
+        err
+    );
+    assert_eq!(err.info(), Some(missing_title));
+}
+
+#[test]
+fn error_conversions() {
+    use std::error::Error;
+    fn parse(text: &str) -> Result<u32, MALError> {
+        Ok(serde_json::from_str(text)?)
+    }
+    let err = parse("nope").unwrap_err();
+    assert!(matches!(err, MALError::Parse { .. }), "{:?}", err);
+    assert_eq!(err.info(), Some(""));
+    assert!(err.source().unwrap().is::<serde_json::Error>());
+
+    let err = MALError::from(std::io::Error::from(std::io::ErrorKind::NotFound));
+    assert_eq!(err.error(), "io_error");
+    assert!(err.source().unwrap().is::<std::io::Error>());
+}
+
+#[test]
+fn truncated_responses_are_errors() {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 4096];
+            let _ = stream.read(&mut buf);
+            //promise more than is sent, then hang up
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 100\r\n\r\n{\"access_to")
+                .unwrap();
+        }
+    });
+
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url.clone();
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(matches!(err, MALError::Read { .. }), "{:?}", err);
+    assert_eq!(err.error(), "read_failed");
+    assert_eq!(err.status(), Some(200));
+    assert!(err.to_string().contains("/anime/80"), "{}", err);
+
+    let mut client = MALClient::with_refresh_token("client_id", "refresh");
+    client.auth_url = url;
+    let err = block_on(client.refresh()).unwrap_err();
+    assert_eq!(err.error(), "read_failed");
+    server.join().unwrap();
+}
+
+#[test]
+fn bulk_update_retries_rate_limited() {
+    let (url, server) = stub_server(vec![
+        (429, r#"{"error": "too_many_requests"}"#),
+        (200, r#"{"status": "completed"}"#),
+        (500, "Internal Server Error"),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let updates = vec![
+        (80, StatusUpdate::new().status(WatchStatus::Completed)),
+        (1, StatusUpdate::new().score(15)),
+        (2, StatusUpdate::new().score(5)),
+    ];
+    let results = block_on(client.update_user_anime_statuses(updates, 1));
+    let ids: Vec<u32> = results.iter().map(|(id, _)| id.0).collect();
+    assert_eq!(ids, vec![80, 1, 2]);
+    assert_eq!(
+        results[0].1.as_ref().unwrap().status,
+        Some(WatchStatus::Completed)
+    );
+    assert!(results[1].1.is_err());
+    assert!(results[2].1.is_err());
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests.len(), 3);
+    assert_eq!(requests[2].url, "/anime/2/my_list_status");
+}
+
+#[cfg(feature = "chrono")]
+#[test]
+fn list_updated_since() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{
+            "data": [
+                {"node": {"id": 1, "title": "New", "main_picture": {}},
+                 "list_status": {"status": "watching", "updated_at": "2022-03-02T10:00:00+00:00"}},
+                {"node": {"id": 2, "title": "Also new", "main_picture": {}},
+                 "list_status": {"status": "completed", "updated_at": "2022-03-01T09:00:00+09:00"}},
+                {"node": {"id": 3, "title": "Old", "main_picture": {}},
+                 "list_status": {"status": "dropped", "updated_at": "2021-12-25T00:00:00+00:00"}}
+            ],
+            "paging": {"next": "http://127.0.0.1:1/never-requested"}
+        }"#,
+    )]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let since = "2022-03-01T00:00:00Z".parse().unwrap();
+    let changed = block_on(client.get_user_anime_list_updated_since(since)).unwrap();
+    let ids: Vec<u32> = changed.iter().map(|n| n.node.id.0).collect();
+    assert_eq!(ids, vec![1, 2]);
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].url.contains("sort=list_updated_at"));
+}
+
+#[test]
+fn offline_refresh_is_error() {
+    let dir = temp_dir("offline_refresh");
+    let expired = Tokens {
+        access_token: "old".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 1,
+        today: 0,
+    };
+    fs::write(dir.join("tokens"), encrypt_token(&expired)).unwrap();
+    let mut builder = ClientBuilder::new().caching(true).cache_dir(dir);
+    //nothing should be listening on port 1
+    builder.auth_url = "http://127.0.0.1:1".to_owned();
+    let res = block_on(builder.build_with_refresh());
+    assert!(matches!(res.err().unwrap(), MALError::Request(_)));
+}
+
+#[test]
+fn auth_url_includes_redirect() {
+    let client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let (url, challenge, state) = client.get_auth_parts("localhost:2525/callback");
+    let url = reqwest::Url::parse(&url).unwrap();
+    let query: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+    assert!(query.contains(&(
+        "redirect_uri".to_owned(),
+        "http://localhost:2525/callback".to_owned()
+    )));
+    assert!(query.contains(&("code_challenge_method".to_owned(), "plain".to_owned())));
+    assert!(query.contains(&("code_challenge".to_owned(), challenge)));
+    assert!(query.contains(&("state".to_owned(), state)));
+    assert!(url
+        .as_str()
+        .contains("redirect_uri=http%3A%2F%2Flocalhost%3A2525%2Fcallback"));
+}
+
+#[test]
+fn auth_bind_failure_is_error() {
+    let taken = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = taken.local_addr().unwrap().to_string();
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let err = block_on(client.auth(&addr, "challenge", "state")).unwrap_err();
+    assert_eq!(err.error(), "bind_failed");
+    assert!(matches!(err, MALError::Auth { .. }));
+    assert!(err.message().unwrap().contains(&addr));
+}
+
+#[test]
+fn auth_times_out() {
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let started = std::time::Instant::now();
+    let err = block_on(client.auth_with_timeout(
+        "127.0.0.1:0",
+        "challenge",
+        "state",
+        Duration::from_millis(100),
+    ))
+    .unwrap_err();
+    assert_eq!(err.error(), "auth_timeout");
+    assert!(started.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn auth_serves_custom_page() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    client.set_auth_response("<h1>Welcome back</h1>", "text/html; charset=utf-8");
+    let addr = free_addr();
+    let browser = send_callback(&addr, "/?code=the_code&state=state");
+
+    block_on(client.auth(&addr, "challenge", "state")).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+    let page = browser.join().unwrap();
+    assert!(page.contains("Content-Type: text/html; charset=utf-8"));
+    assert!(page.ends_with("<h1>Welcome back</h1>"));
+    assert!(server.join().unwrap()[0].body.contains("code=the_code"));
+}
+
+#[test]
+fn callback_parsing() {
+    let code = parse_callback("/?code=abc%2F123&state=bruh42", "bruh42");
+    assert_eq!(code.unwrap(), "abc/123");
+    let err = parse_callback("/?code=abc&state=bruh7", "bruh42").unwrap_err();
+    assert_eq!(err.error(), "state_mismatch");
+    let err = parse_callback("/favicon.ico", "bruh42").unwrap_err();
+    assert_eq!(err.error(), "state_mismatch");
+
+    let err = parse_callback(
+        "/?error=access_denied&error_description=The+user+denied+the+request&state=bruh42",
+        "bruh42",
+    )
+    .unwrap_err();
+    assert_eq!(err.error(), "access_denied");
+    assert_eq!(err.message(), Some("The user denied the request"));
+
+    let err = parse_callback("/?state=bruh42", "bruh42").unwrap_err();
+    assert_eq!(err.error(), "missing_code");
+}
+
+#[test]
+fn auth_state_is_random() {
+    let client = MALClient::with_access_token("token");
+    let (_, _, first) = client.get_auth_parts("localhost:2525");
+    let (_, _, second) = client.get_auth_parts("localhost:2525");
+    assert_eq!(first.len(), 64);
+    assert_ne!(first, second);
+}
+
+#[test]
+fn bogus_callbacks_give_up() {
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let addr = free_addr();
+    let browser = {
+        let addr = addr.clone();
+        thread::spawn(move || {
+            for _ in 0..10 {
+                send_callback(&addr, "/?code=forged&state=wrong")
+                    .join()
+                    .unwrap();
+            }
+        })
+    };
+    let err =
+        block_on(client.auth_with_timeout(&addr, "challenge", "state", Duration::from_secs(5)))
+            .unwrap_err();
+    assert_eq!(err.error(), "state_mismatch");
+    browser.join().unwrap();
+}
+
+#[test]
+fn denied_auth_skips_token_exchange() {
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    //nothing should be listening here, the exchange must not be attempted
+    client.auth_url = format!("http://{}", free_addr());
+    let addr = free_addr();
+    let browser = send_callback(&addr, "/?error=access_denied&state=state");
+
+    let err = block_on(client.auth(&addr, "challenge", "state")).unwrap_err();
+    assert_eq!(err.error(), "access_denied");
+    assert!(browser.join().unwrap().contains("Unable to log in"));
+}
+
+#[test]
+fn auth_does_not_block_executor() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    let (url, _server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    let addr = free_addr();
+    //the browser only shows up once another task on the same thread has run
+    let ready = Arc::new(AtomicBool::new(false));
+    let browser = {
+        let ready = ready.clone();
+        let addr = addr.clone();
+        thread::spawn(move || {
+            while !ready.load(Ordering::SeqCst) {
+                thread::sleep(Duration::from_millis(10));
+            }
+            send_callback(&addr, "/?code=the_code&state=state")
+                .join()
+                .unwrap()
+        })
+    };
+
+    let (res, _) = block_on(async {
+        futures::join!(
+            client.auth_with_timeout(&addr, "challenge", "state", Duration::from_secs(5)),
+            async {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                ready.store(true, Ordering::SeqCst);
+            }
+        )
+    });
+    res.unwrap();
+    assert!(browser.join().unwrap().contains("logged in"));
+}
+
+#[test]
+fn cancelled_auth_releases_port() {
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let addr = free_addr();
+    let err =
+        block_on(client.auth_with_timeout(&addr, "challenge", "state", Duration::from_millis(50)))
+            .unwrap_err();
+    assert_eq!(err.error(), "auth_timeout");
+
+    wait_for_port_free(&addr);
+}
+
+#[cfg(feature = "https-callback")]
+#[test]
+fn auth_over_https() {
+    let (url, _server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    client.set_callback_tls(
+        include_bytes!("test-data/callback-cert.pem").to_vec(),
+        include_bytes!("test-data/callback-key.pem").to_vec(),
+    );
+    let addr = free_addr();
+    let redirect = format!("https://{}", addr);
+    let callback = format!("{}/?code=the_code&state=state", redirect);
+
+    let (res, page) = block_on(async {
+        let browser = async {
+            let browser = reqwest::Client::builder()
+                .danger_accept_invalid_certs(true)
+                .build()
+                .unwrap();
+            loop {
+                match browser.get(&callback).send().await {
+                    Ok(res) => break res.text().await.unwrap(),
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        futures::join!(
+            client.auth_with_timeout(&redirect, "challenge", "state", Duration::from_secs(5)),
+            browser
+        )
+    });
+    res.unwrap();
+    assert!(page.contains("logged in"));
+    assert_eq!(client.get_access_token(), "new");
+}
+
+#[test]
+fn ephemeral_port_listener() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    let listener = client.start_listener("localhost:0").unwrap();
+    let port = listener.local_addr().port();
+    assert_ne!(port, 0);
+    let redirect = listener.redirect_uri().to_owned();
+    assert_eq!(redirect, format!("http://localhost:{}", port));
+
+    let browser = send_callback(
+        &listener.local_addr().to_string(),
+        "/?code=the_code&state=state",
+    );
+    block_on(client.wait_for_callback(listener, "challenge", "state", Duration::from_secs(5)))
+        .unwrap();
+    browser.join().unwrap();
+    let requests = server.join().unwrap();
+    assert!(requests[0]
+        .body
+        .ends_with(&format!("redirect_uri=http%3A%2F%2Flocalhost%3A{}", port)));
+}
+
+#[test]
+fn listener_matches_callback_path() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    let listener = client.start_listener("127.0.0.1:0/mal/callback").unwrap();
+    let addr = listener.local_addr().to_string();
+    assert_eq!(
+        listener.redirect_uri(),
+        format!("http://{}/mal/callback", addr)
+    );
+
+    let browser = {
+        let addr = addr.clone();
+        thread::spawn(move || {
+            let favicon = send_callback(&addr, "/favicon.ico").join().unwrap();
+            let wrong_path = send_callback(&addr, "/?code=the_code&state=state")
+                .join()
+                .unwrap();
+            send_callback(&addr, "/mal/callback?code=the_code&state=state")
+                .join()
+                .unwrap();
+            (favicon, wrong_path)
+        })
+    };
+    block_on(client.wait_for_callback(listener, "challenge", "state", Duration::from_secs(5)))
+        .unwrap();
+    let (favicon, wrong_path) = browser.join().unwrap();
+    assert!(favicon.starts_with("HTTP/1.1 404"));
+    assert!(wrong_path.starts_with("HTTP/1.1 404"));
+    assert!(server.join().unwrap()[0].body.contains("code=the_code"));
+}
+
+#[test]
+fn cancel_auth() {
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    let addr = free_addr();
+    let (login, handle) = client.auth_cancellable(&addr, "challenge", "state");
+    let canceller = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(50));
+        handle.cancel();
+    });
+    let err = block_on(login).unwrap_err();
+    assert_eq!(err.error(), "auth_cancelled");
+    wait_for_port_free(&addr);
+    canceller.join().unwrap();
+
+    //dropping the handle without cancelling keeps waiting
+    let (url, _server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    client.auth_url = url;
+    let browser = send_callback(&addr, "/?code=the_code&state=state");
+    let (login, handle) = client.auth_cancellable(&addr, "challenge", "state");
+    drop(handle);
+    block_on(login).unwrap();
+    browser.join().unwrap();
+}
+
+#[test]
+fn code_exchange_sends_redirect() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    client.need_auth = true;
+    block_on(client.auth_with_code("code", "verifier", "localhost:2525")).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+    assert!(!client.need_auth);
+
+    let requests = server.join().unwrap();
+    assert_eq!(
+        requests[0].body,
+        "client_id=client_id&grant_type=authorization_code&code_verifier=verifier\
+         &code=code&redirect_uri=http%3A%2F%2Flocalhost%3A2525"
+    );
+}
+
+#[test]
+fn pasted_redirect_auth() {
+    let token = r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#;
+    let (url, server) = stub_server(vec![(200, token), (200, token)]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+
+    let err = block_on(client.auth_from_redirect_url(
+        "http://localhost:2525/?code=pasted&state=other",
+        "localhost:2525",
+        "challenge",
+        "state",
+    ))
+    .unwrap_err();
+    assert_eq!(err.error(), "state_mismatch");
+
+    block_on(client.auth_from_redirect_url(
+        "http://localhost:2525/?code=pasted&state=state\n",
+        "localhost:2525",
+        "challenge",
+        "state",
+    ))
+    .unwrap();
+    block_on(client.auth_from_redirect_url(
+        " just_the_code ",
+        "localhost:2525",
+        "challenge",
+        "state",
+    ))
+    .unwrap();
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].body.contains("&code=pasted&"));
+    assert!(requests[1].body.contains("&code=just_the_code&"));
+}
+
+#[test]
+fn rejected_code_is_mal_error() {
+    let (url, _server) = stub_server(vec![(
+        400,
+        r#"{"error": "invalid_grant", "message": "The authorization code is invalid"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    let err = block_on(client.auth_with_code("bad", "verifier", "localhost:2525")).unwrap_err();
+    assert!(matches!(err, MALError::Auth { .. }));
+    assert_eq!(err.error(), "invalid_grant");
+}
+
+#[test]
+fn confidential_client_sends_secret() {
+    let token = r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#;
+    let (url, server) = stub_server(vec![(200, token), (200, token)]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .client_secret("shh".to_owned())
+        .build_no_refresh();
+    client.auth_url = url;
+    block_on(client.auth_with_code("code", "verifier", "localhost:2525")).unwrap();
+    block_on(client.refresh()).unwrap();
+
+    let requests = server.join().unwrap();
+    assert!(requests[0].body.ends_with("&client_secret=shh"));
+    assert_eq!(
+        requests[1].body,
+        "grant_type=refresh_token&refresh_token=new_refresh&client_id=client_id&client_secret=shh"
+    );
+}
+
+#[test]
+fn refresh_sends_expected_form() {
+    let dir = temp_dir("refresh_form");
+    let expired = Tokens {
+        access_token: "old".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 1,
+        today: 0,
+    };
+    fs::write(dir.join("tokens"), encrypt_token(&expired)).unwrap();
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut builder = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .caching(true)
+        .cache_dir(dir.clone());
+    builder.auth_url = url;
+    let client = block_on(builder.build_with_refresh()).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].url, "/token");
+    assert_eq!(
+        requests[0].body,
+        "grant_type=refresh_token&refresh_token=refresh&client_id=client_id"
+    );
+    let (cached, _) = decode_cache(&fs::read(dir.join("tokens")).unwrap(), None).unwrap();
+    assert_eq!(cached.refresh_token(), "new_refresh");
+}
+
+#[test]
+fn token_refresh_hook() {
+    let dir = temp_dir("refresh_hook");
+    fs::write(
+        dir.join("tokens"),
+        encrypt_token(&Tokens::new(
+            "old".to_owned(),
+            "refresh".to_owned(),
+            SystemTime::now(),
+        )),
+    )
+    .unwrap();
+    let (url, _server) = stub_server(vec![
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "first"}"#,
+        ),
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "newer", "refresh_token": "second"}"#,
+        ),
+    ]);
+    let seen = std::sync::Arc::new(std::sync::Mutex::new(vec![]));
+    let hook_seen = seen.clone();
+    let mut builder = ClientBuilder::new()
+        .caching(true)
+        .cache_dir(dir)
+        .on_token_refresh(move |tokens| {
+            hook_seen
+                .lock()
+                .unwrap()
+                .push(tokens.refresh_token().to_owned())
+        });
+    builder.auth_url = url;
+    let mut client = block_on(builder.build_with_refresh()).unwrap();
+    assert_eq!(*seen.lock().unwrap(), ["first"]);
+
+    block_on(client.auth_with_code("code", "verifier", "localhost:2561")).unwrap();
+    assert_eq!(*seen.lock().unwrap(), ["first", "second"]);
+}
+
+#[test]
+fn rejected_refresh_is_mal_error() {
+    let dir = temp_dir("rejected_refresh");
+    let expired = Tokens {
+        access_token: "old".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 1,
+        today: 0,
+    };
+    fs::write(dir.join("tokens"), encrypt_token(&expired)).unwrap();
+    let (url, _server) = stub_server(vec![(
+        400,
+        r#"{"error": "invalid_grant", "message": "The refresh token is invalid."}"#,
+    )]);
+    let mut builder = ClientBuilder::new().caching(true).cache_dir(dir);
+    builder.auth_url = url;
+    let err = block_on(builder.build_with_refresh()).err().unwrap();
+    assert_eq!(err.error(), "invalid_grant");
+}
+
+#[test]
+fn secrets_are_redacted() {
+    let expires_at = SystemTime::now() + Duration::from_secs(3600);
+    let tokens = Tokens::new(
+        "secret_access".to_owned(),
+        "secret_refresh".to_owned(),
+        expires_at,
+    );
+    let mut client = MALClient::from_tokens("client_id", tokens);
+    client.set_client_secret("secret_client".to_owned());
+    let tokens = client.tokens().unwrap();
+    for debug in [format!("{:?}", client), format!("{:?}", tokens)] {
+        assert!(debug.contains("[redacted]"), "{}", debug);
+        assert!(!debug.contains("secret_"), "{}", debug);
+    }
+    assert_eq!(client.get_access_token(), "secret_access");
+
+    //a token response that doesn't parse still mustn't leak the tokens into the error
+    let (url, _server) = stub_server(vec![
+        (
+            200,
+            r#"{"access_token": "secret_new", "refresh_token": "secret_new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "refresh_token": "secret_new_refr"#,
+        ),
+    ]);
+    client.auth_url = url;
+    let err = block_on(client.refresh()).unwrap_err();
+    let info = err.info().unwrap();
+    assert!(info.contains("access_token"), "{}", info);
+    assert!(!info.contains("secret_"), "{}", info);
+
+    let err = block_on(client.refresh()).unwrap_err();
+    assert!(matches!(err, MALError::Parse { .. }), "{:?}", err);
+    assert!(err.info().unwrap().contains("expires_in"));
+    for formatted in [format!("{:?}", err), err.to_string()] {
+        assert!(!formatted.contains("secret_"), "{}", formatted);
+    }
+}
+
+#[test]
+fn refresh_without_refresh_token() {
+    let client = MALClient::with_access_token("token");
+    let err = block_on(client.refresh()).unwrap_err();
+    assert_eq!(err.error(), "no_refresh_token");
+}
+
+#[test]
+fn retry_after_unauthorized() {
+    let (url, server) = stub_server(vec![
+        (401, r#"{"error": "invalid_token"}"#),
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_access_token("expired");
+    client.api_url = url.clone();
+    client.auth_url = url;
+    *client.refresh_token.get_mut().unwrap() = "refresh".to_owned().into();
+    let anime = block_on(client.get_anime_details(80, AnimeFields::Title)).unwrap();
+    assert_eq!(anime.show.title, "Mobile Suit Gundam");
+    assert_eq!(client.get_access_token(), "new");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[1].url, "/token");
+    assert_eq!(requests[2].url, "/anime/80?fields=title");
+}
+
+#[test]
+fn unauthorized_without_refresh_token() {
+    let (url, _server) = stub_server(vec![(401, r#"{"error": "invalid_token"}"#)]);
+    let mut client = MALClient::with_access_token("expired");
+    client.api_url = url;
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert_eq!(err.error(), "unauthorized");
+}
+
+#[test]
+fn proactive_refresh_happens_once() {
+    let details = r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#;
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (200, details),
+        (200, details),
+    ]);
+    let mut client = MALClient::with_access_token("expiring");
+    client.api_url = url.clone();
+    client.auth_url = url;
+    client.set_refresh_margin(Duration::from_secs(60));
+    *client.refresh_token.get_mut().unwrap() = "refresh".to_owned().into();
+    *client.expires_at.get_mut().unwrap() = Some(SystemTime::now() + Duration::from_secs(30));
+
+    let (first, second) = block_on(async {
+        futures::join!(
+            client.get_anime_details(80, AnimeFields::Title),
+            client.get_anime_details(80, AnimeFields::Title)
+        )
+    });
+    assert!(first.is_ok() && second.is_ok());
+    assert_eq!(client.get_access_token(), "new");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/token");
+    assert_eq!(requests.iter().filter(|r| r.url == "/token").count(), 1);
+}
+
+#[test]
+fn refresh_token_client_refreshes_first() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_refresh_token("client_id", "stored_refresh");
+    client.api_url = url.clone();
+    client.auth_url = url;
+    assert!(!client.need_auth);
+
+    block_on(client.get_anime_details(80, AnimeFields::Title)).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+    assert!(!client.is_token_expired());
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/token");
+    assert!(requests[0].body.contains("refresh_token=stored_refresh"));
+    assert!(requests[0].body.contains("client_id=client_id"));
+}
+
+#[test]
+fn builder_options() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+    ]);
+    let mut builder = MALClient::builder()
+        .client_id("client_id".to_owned())
+        .refresh_token("stored_refresh".to_owned())
+        .user_agent("lib-mal-test/1.0".to_owned())
+        .timeout(Duration::from_secs(5));
+    builder.auth_url = url.clone();
+    let mut client = block_on(builder.build()).unwrap();
+    client.api_url = url;
+    assert!(client.is_token_expired());
+
+    block_on(client.get_anime_details(80, AnimeFields::Title)).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/token");
+    for req in &requests {
+        assert_eq!(req.user_agent.as_deref(), Some("lib-mal-test/1.0"));
+    }
+}
+
+#[test]
+fn builder_http_client() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+    ]);
+    let http = reqwest::Client::builder()
+        .user_agent("injected/1.0")
+        .build()
+        .unwrap();
+    let mut builder = MALClient::builder()
+        .client_id("client_id".to_owned())
+        .refresh_token("stored_refresh".to_owned())
+        .user_agent("ignored/1.0".to_owned())
+        .http_client(http);
+    builder.auth_url = url.clone();
+    let mut client = block_on(builder.build()).unwrap();
+    client.api_url = url;
+    block_on(client.get_anime_details(80, AnimeFields::Title)).unwrap();
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/token");
+    for req in &requests {
+        assert_eq!(req.user_agent.as_deref(), Some("injected/1.0"));
+    }
+}
+
+#[test]
+fn builder_timeout() {
+    //accepts the connection but never answers
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let mut client = block_on(
+        MALClient::builder()
+            .access_token("token".to_owned())
+            .timeout(Duration::from_millis(200))
+            .build(),
+    )
+    .unwrap();
+    client.api_url = format!("http://{}", listener.local_addr().unwrap());
+    let err = block_on(client.get_anime_details(80, None)).unwrap_err();
+    assert!(
+        matches!(err, MALError::Request(ref e) if e.is_timeout()),
+        "{:?}",
+        err
+    );
+}
+
+#[test]
+fn builder_rejects_bad_user_agent() {
+    let res = block_on(
+        MALClient::builder()
+            .access_token("token".to_owned())
+            .user_agent("bad\nagent".to_owned())
+            .build(),
+    );
+    assert!(matches!(res.err().unwrap(), MALError::Request(_)));
+}
+
+#[test]
+fn request_futures_are_send() {
+    fn assert_send<T: Send>(_: T) {}
+    let client = MALClient::with_access_token("token");
+    assert_send(client.get_anime_details(80, None));
+    assert_send(client.update_user_anime_status(80, StatusUpdate::new()));
+    assert_send(client.refresh());
+    assert_send(client.list_profiles());
+    #[cfg(feature = "open-browser")]
+    assert_send(MALClient::with_access_token("token").auth_interactive(
+        "localhost:0",
+        None,
+        |_| {},
+    ));
+}
+
+///Never run, it only has to compile to show the response types are still inferred from the
+///method that's called
+#[allow(dead_code)]
+async fn response_types_are_inferred(client: &MALClient) -> Result<(), MALError> {
+    let list = client.get_anime_list("one", None, None).await?;
+    let details = client.get_anime_details(80, None).await?;
+    let status = client
+        .update_user_anime_status(80, StatusUpdate::new())
+        .await?;
+    let boards = client.get_forum_boards().await?;
+    let _: (AnimeList, AnimeDetails, ListStatus) = (list, details, status);
+    let _: Vec<_> = boards.categories;
+    Ok(())
+}
+
+#[test]
+fn models_are_clone_send_sync() {
+    use crate::model::*;
+    fn assert_model<T: Clone + Send + Sync>() {}
+    assert_model::<AnimeList>();
+    assert_model::<AnimeDetails>();
+    assert_model::<ListStatus>();
+    assert_model::<User>();
+    assert_model::<ForumBoards>();
+    assert_model::<TopicDetails>();
+    assert_model::<ForumTopics>();
+    assert_model::<StatusUpdate>();
+}
+
+#[test]
+fn tokens_round_trip() {
+    let expires_at = SystemTime::now() + Duration::from_secs(3600);
+    let tokens = Tokens::new("access".to_owned(), "refresh".to_owned(), expires_at);
+    let json = serde_json::to_string(&tokens).unwrap();
+    let tokens: Tokens = serde_json::from_str(&json).unwrap();
+
+    let client = MALClient::from_tokens("client_id", tokens);
+    assert!(!client.need_auth);
+    assert_eq!(client.get_access_token(), "access");
+    let saved = client.tokens().unwrap();
+    assert_eq!(saved.access_token(), "access");
+    assert_eq!(saved.refresh_token(), "refresh");
+    let drift = saved
+        .expires_at()
+        .duration_since(expires_at)
+        .unwrap_or_else(|e| e.duration());
+    assert!(drift < Duration::from_secs(1));
+
+    assert!(MALClient::with_access_token("token").tokens().is_none());
+}
+
+#[test]
+fn token_expiry() {
+    let mut client = MALClient::with_access_token("token");
+    assert_eq!(client.expires_at(), None);
+    assert!(!client.is_token_expired());
+
+    let past = SystemTime::now() - Duration::from_secs(1);
+    *client.expires_at.get_mut().unwrap() = Some(past);
+    assert_eq!(client.expires_at(), Some(past));
+    assert!(client.is_token_expired());
+
+    *client.expires_at.get_mut().unwrap() = Some(SystemTime::now() + Duration::from_secs(60));
+    assert!(!client.is_token_expired());
+}
+
+#[derive(Default)]
+struct MemoryStore(std::sync::Mutex<Option<Tokens>>);
+
+#[async_trait::async_trait]
+impl TokenStore for MemoryStore {
+    async fn load(&self) -> Result<Option<Tokens>, crate::MALError> {
+        Ok(self.0.lock().unwrap().clone())
+    }
+
+    async fn save(&self, tokens: &Tokens) -> Result<(), crate::MALError> {
+        *self.0.lock().unwrap() = Some(tokens.clone());
+        Ok(())
+    }
+
+    async fn clear(&self) -> Result<(), crate::MALError> {
+        *self.0.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+#[test]
+fn custom_token_store() {
+    let store = std::sync::Arc::new(MemoryStore::default());
+    let expired = Tokens {
+        access_token: "old".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 1,
+        today: 0,
+    };
+    block_on(store.save(&expired)).unwrap();
+    let (url, _server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut builder = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .token_store(Box::new(store.clone()));
+    builder.auth_url = url;
+    let mut client = block_on(builder.build_with_refresh()).unwrap();
+    assert!(!client.need_auth);
+    assert_eq!(client.get_access_token(), "new");
+    let saved = block_on(store.load()).unwrap().unwrap();
+    assert_eq!(saved.refresh_token(), "new_refresh");
+
+    block_on(client.logout()).unwrap();
+    assert!(block_on(store.load()).unwrap().is_none());
+}
+
+///A store that can't save anything, like a locked keyring
+struct FailingStore;
+
+#[async_trait::async_trait]
+impl TokenStore for FailingStore {
+    async fn load(&self) -> Result<Option<Tokens>, crate::MALError> {
+        Ok(None)
+    }
+
+    async fn save(&self, _tokens: &Tokens) -> Result<(), crate::MALError> {
+        Err(crate::MALError::new(
+            "The store is read only",
+            "store_failed",
+            None,
+        ))
+    }
+
+    async fn clear(&self) -> Result<(), crate::MALError> {
+        Ok(())
+    }
+}
+
+#[test]
+fn failed_save_keeps_login() {
+    let (url, server) = stub_server(vec![(
+        200,
+        r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+    )]);
+    let mut client = ClientBuilder::new()
+        .client_id("client_id".to_owned())
+        .token_store(Box::new(FailingStore))
+        .build_no_refresh();
+    client.auth_url = url;
+    client.need_auth = true;
+    block_on(client.auth_with_code("code", "verifier", "localhost:2525")).unwrap();
+    assert_eq!(client.get_access_token(), "new");
+    assert!(!client.need_auth);
+    assert_eq!(client.take_save_error().unwrap().error(), "store_failed");
+    assert!(client.take_save_error().is_none());
+    server.join().unwrap();
+}
+
+#[test]
+fn failed_save_keeps_retry() {
+    let (url, server) = stub_server(vec![
+        (401, r#"{"error": "invalid_token"}"#),
+        (
+            200,
+            r#"{"token_type": "Bearer", "expires_in": 2678400, "access_token": "new", "refresh_token": "new_refresh"}"#,
+        ),
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}}"#,
+        ),
+    ]);
+    let tokens = Tokens::new(
+        "old".to_owned(),
+        "refresh".to_owned(),
+        SystemTime::now() + Duration::from_secs(3600),
+    );
+    let mut client = MALClient::from_tokens("client_id", tokens);
+    client.set_token_store(Box::new(FailingStore));
+    client.api_url = url.clone();
+    client.auth_url = url;
+    let anime = block_on(client.get_anime_details(80, AnimeFields::Title)).unwrap();
+    assert_eq!(anime.id, 80);
+    assert_eq!(client.get_access_token(), "new");
+    assert_eq!(client.take_save_error().unwrap().error(), "store_failed");
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[1].url, "/token");
+}
+
+#[cfg(feature = "keyring")]
+#[test]
+fn keyring_store_without_entry_needs_auth() {
+    let profile = format!("test-{}", std::process::id());
+    let builder = ClientBuilder::new()
+        .client_id("lib-mal-tests".to_owned())
+        .token_store(Box::new(crate::store::KeyringTokenStore::new(
+            "lib-mal-tests",
+            &profile,
+        )));
+    //whether there's no entry or no keyring at all, the user has to log in
+    let client = block_on(builder.build_with_refresh()).unwrap();
+    assert!(client.need_auth);
+}
+
+#[test]
+fn secret_derived_cache_key() {
+    let dir = temp_dir("secret_store");
+    let tokens = Tokens {
+        access_token: "access".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 60,
+        today: 100,
+    };
+    let store = crate::store::FileTokenStore::with_secret(dir.clone(), "app secret");
+    block_on(store.save(&tokens)).unwrap();
+    let raw = fs::read(dir.join("tokens")).unwrap();
+    assert!(decrypt_tokens(&raw).is_err());
+    assert_eq!(
+        block_on(store.load()).unwrap().unwrap().refresh_token(),
+        "refresh"
+    );
+
+    let wrong = crate::store::FileTokenStore::with_secret(dir.clone(), "other secret");
+    assert_eq!(
+        block_on(wrong.load()).map(|_| ()).unwrap_err().error(),
+        "cache_unreadable"
+    );
+
+    //a cache the user can't read with the secret means logging in again
+    let client = block_on(
+        ClientBuilder::new()
+            .client_id("client_id".to_owned())
+            .client_secret("other secret".to_owned())
+            .caching(true)
+            .cache_dir(dir.clone())
+            .build_with_refresh(),
+    )
+    .unwrap();
+    assert!(client.need_auth);
+
+    //caches written without a secret can still be read
+    fs::write(dir.join("tokens"), encrypt_token(&tokens)).unwrap();
+    assert_eq!(
+        block_on(store.load()).unwrap().unwrap().access_token(),
+        "access"
+    );
+}
+
+#[test]
+fn plain_file_store() {
+    let dir = temp_dir("plain_store");
+    let legacy = Tokens {
+        access_token: "old".to_owned().into(),
+        refresh_token: "refresh".to_owned().into(),
+        expires_in: 60,
+        today: 100,
+    };
+    fs::write(dir.join("tokens"), encrypt_token(&legacy)).unwrap();
+    let store = crate::store::PlainFileTokenStore::new(dir.clone());
+    let loaded = block_on(store.load()).unwrap().unwrap();
+    assert_eq!(loaded.refresh_token(), "refresh");
+
+    block_on(store.save(&loaded)).unwrap();
+    let json: serde_json::Value =
+        serde_json::from_slice(&fs::read(dir.join("tokens")).unwrap()).unwrap();
+    assert_eq!(
+        json,
+        serde_json::json!({
+            "access_token": "old",
+            "refresh_token": "refresh",
+            "expires_in": 60,
+            "today": 100
+        })
+    );
+    assert_eq!(
+        block_on(store.load()).unwrap().unwrap().access_token(),
+        "old"
+    );
+
+    block_on(store.clear()).unwrap();
+    assert!(block_on(store.load()).unwrap().is_none());
+}
+
+#[test]
+fn cache_dir_is_created() {
+    let dir = temp_dir("nested_cache")
+        .join("config")
+        .join("myapp")
+        .join("mal");
+    let tokens = Tokens::new("access".to_owned(), "refresh".to_owned(), SystemTime::now());
+    let store = crate::store::FileTokenStore::new(dir.clone());
+    block_on(store.save(&tokens)).unwrap();
+    assert!(dir.join("tokens").exists());
+    assert_eq!(
+        block_on(store.load()).unwrap().unwrap().refresh_token(),
+        "refresh"
+    );
+
+    //a file where the directory should be can't be fixed, but it's an error not a panic
+    let blocked = temp_dir("blocked_cache");
+    fs::write(blocked.join("file"), "").unwrap();
+    let store = crate::store::FileTokenStore::new(blocked.join("file").join("mal"));
+    assert!(block_on(store.save(&tokens)).is_err());
+}
+
+#[test]
+fn legacy_cache_is_upgraded() {
+    for (name, secret) in [("cache_v1", None), ("cache_v1_secret", Some("app secret"))] {
+        let dir = temp_dir(name);
+        fs::write(dir.join("tokens"), include_bytes!("test-data/tokens-v1")).unwrap();
+        let store = match secret {
+            Some(secret) => crate::store::FileTokenStore::with_secret(dir.clone(), secret),
+            None => crate::store::FileTokenStore::new(dir.clone()),
+        };
+        let loaded = block_on(store.load()).unwrap().unwrap();
+        assert_eq!(loaded.access_token(), "legacy_access");
+        assert_eq!(loaded.refresh_token(), "legacy_refresh");
+        assert_eq!(loaded.expires_in, 2678400);
+        assert_eq!(loaded.today, 1650000000);
+
+        let raw = fs::read(dir.join("tokens")).unwrap();
+        assert!(raw.starts_with(b"lmal"));
+        let (upgraded, version) = decode_cache(&raw, secret).unwrap();
+        assert_eq!(version, CACHE_VERSION);
+        assert_eq!(upgraded.refresh_token(), "legacy_refresh");
+        assert_eq!(secret.is_some(), decode_cache(&raw, None).is_err());
+    }
+}
+
+#[test]
+fn corrupt_cache_needs_auth() {
+    let tokens = Tokens::new("access".to_owned(), "refresh".to_owned(), SystemTime::now());
+    let cache = crate::store::encode_cache(&tokens, None);
+    let legacy = include_bytes!("test-data/tokens-v1");
+    let files: [(&str, &[u8]); 5] = [
+        ("cache_garbage", b"definitely not a token cache"),
+        ("cache_empty", b""),
+        ("cache_header", b"lmal"),
+        ("cache_truncated", &cache[..cache.len() / 2]),
+        ("cache_truncated_v1", &legacy[..legacy.len() - 5]),
+    ];
+    for (name, contents) in files {
+        let dir = temp_dir(name);
+        fs::write(dir.join("tokens"), contents).unwrap();
+        let client = block_on(
+            ClientBuilder::new()
+                .caching(true)
+                .cache_dir(dir.clone())
+                .build_with_refresh(),
+        )
+        .expect("Corrupt cache should not be an error");
+        assert!(client.need_auth, "{}", name);
+        assert_eq!(client.get_access_token(), "");
+        assert!(client.cache_error().is_some(), "{}", name);
+    }
+
+    let dir = temp_dir("cache_fine");
+    block_on(
+        crate::store::FileTokenStore::new(dir.clone()).save(&Tokens::new(
+            "access".to_owned(),
+            "refresh".to_owned(),
+            SystemTime::now() + Duration::from_secs(3600),
+        )),
+    )
+    .unwrap();
+    let client = block_on(
+        ClientBuilder::new()
+            .caching(true)
+            .cache_dir(dir)
+            .build_with_refresh(),
+    )
+    .unwrap();
+    assert!(!client.need_auth);
+    assert!(client.cache_error().is_none());
+}
+
+#[test]
+fn unknown_cache_version_needs_auth() {
+    let dir = temp_dir("cache_future");
+    fs::write(dir.join("tokens"), b"lmal\x09\x00whatever comes next").unwrap();
+    let store = crate::store::FileTokenStore::new(dir.clone());
+    assert_eq!(
+        block_on(store.load()).map(|_| ()).unwrap_err().error(),
+        "cache_unreadable"
+    );
+
+    let client = block_on(
+        ClientBuilder::new()
+            .caching(true)
+            .cache_dir(dir.clone())
+            .build_with_refresh(),
+    )
+    .unwrap();
+    assert!(client.need_auth);
+    //left alone in case a newer version of the app wants it back
+    assert!(fs::read(dir.join("tokens"))
+        .unwrap()
+        .starts_with(b"lmal\x09"));
+}
+
+#[test]
+fn token_profiles() {
+    let dir = temp_dir("profiles");
+    let in_a_day = SystemTime::now() + Duration::from_secs(86400);
+    for name in ["alice", "bob"] {
+        let tokens = Tokens::new(
+            format!("{}_access", name),
+            format!("{}_refresh", name),
+            in_a_day,
+        );
+        let store = crate::store::FileTokenStore::new(dir.clone()).profile(name);
+        block_on(store.save(&tokens)).unwrap();
+    }
+    let mut client = block_on(
+        ClientBuilder::new()
+            .caching(true)
+            .cache_dir(dir.clone())
+            .profile("alice".to_owned())
+            .build_with_refresh(),
+    )
+    .unwrap();
+    assert_eq!(client.profile(), "alice");
+    assert_eq!(client.get_access_token(), "alice_access");
+    assert_eq!(block_on(client.list_profiles()).unwrap(), ["alice", "bob"]);
+
+    block_on(client.switch_profile("bob")).unwrap();
+    assert!(!client.need_auth);
+    assert_eq!(client.get_access_token(), "bob_access");
+    assert_eq!(**client.refresh_token.read().unwrap(), "bob_refresh");
+
+    let err = block_on(client.switch_profile("../alice")).unwrap_err();
+    assert_eq!(err.error(), "invalid_profile");
+    assert_eq!(client.profile(), "bob");
+
+    //a new profile needs to log in, and saves into its own entry
+    block_on(client.switch_profile(DEFAULT_PROFILE)).unwrap();
+    assert!(client.need_auth);
+    assert!(client.get_access_token().is_empty());
+    let tokens = Tokens::new("default_access".to_owned(), "r".to_owned(), in_a_day);
+    block_on(crate::store::FileTokenStore::new(dir.clone()).save(&tokens)).unwrap();
+    assert!(dir.join("tokens").exists());
+    assert_eq!(
+        block_on(client.list_profiles()).unwrap(),
+        ["alice", "bob", DEFAULT_PROFILE]
+    );
+
+    block_on(client.delete_profile("bob")).unwrap();
+    assert_eq!(
+        block_on(client.list_profiles()).unwrap(),
+        ["alice", DEFAULT_PROFILE]
+    );
+    block_on(client.delete_profile(DEFAULT_PROFILE)).unwrap();
+    assert!(client.need_auth);
+    assert_eq!(block_on(client.list_profiles()).unwrap(), ["alice"]);
+}
+
+#[cfg(unix)]
+#[test]
+fn token_file_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = |path: &std::path::Path| fs::metadata(path).unwrap().permissions().mode() & 0o777;
+    let parent = temp_dir("cache_permissions");
+    fs::set_permissions(&parent, fs::Permissions::from_mode(0o755)).unwrap();
+    let dir = parent.join("mal");
+    let tokens = Tokens::new("access".to_owned(), "refresh".to_owned(), SystemTime::now());
+    let store = crate::store::FileTokenStore::new(dir.clone());
+    block_on(store.save(&tokens)).unwrap();
+    assert_eq!(mode(&dir), 0o700);
+    assert_eq!(mode(&dir.join("tokens")), 0o600);
+    assert_eq!(mode(&parent), 0o755);
+
+    //files left readable by an older version are fixed on the next save
+    fs::set_permissions(dir.join("tokens"), fs::Permissions::from_mode(0o644)).unwrap();
+    block_on(crate::store::PlainFileTokenStore::new(dir.clone()).save(&tokens)).unwrap();
+    assert_eq!(mode(&dir.join("tokens")), 0o600);
+}
+
+#[test]
+fn logout_clears_tokens() {
+    let dir = temp_dir("logout");
+    fs::write(dir.join("tokens"), "cached").unwrap();
+    let mut client = MALClient::with_access_token("token");
+    *client.refresh_token.get_mut().unwrap() = "refresh".to_owned().into();
+    client.set_cache_dir(dir.clone());
+    client.set_caching(true);
+
+    block_on(client.logout()).unwrap();
+    assert!(client.need_auth);
+    assert!(client.get_access_token().is_empty());
+    assert!(client.refresh_token.read().unwrap().is_empty());
+    assert!(!dir.join("tokens").exists());
+
+    //logging out again without a cache file is fine
+    block_on(client.logout()).unwrap();
+}
+
+#[test]
+fn set_score_out_of_range() {
+    let client = MALClient::with_access_token("");
+    for score in [0, 11] {
+        let err = block_on(client.set_score(80, score)).expect_err("Score should be rejected");
+        assert_eq!(err.error(), "invalid_score");
+    }
+    //0 clears the score in a plain update, but set_score is only for giving one
+    assert!(StatusUpdate::new().score(0).validate().is_ok());
+}
+
+#[test]
+fn set_score_sends_score() {
+    let (url, server) = stub_server(vec![(200, r#"{"status": "watching", "score": 9}"#)]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.set_score(80, 9)).unwrap();
+    assert_eq!(status.score, Some(9));
+    assert_eq!(status.status, Some(WatchStatus::Watching));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "PATCH");
+    assert_eq!(requests[0].url, "/anime/80/my_list_status");
+    assert_eq!(requests[0].body, "score=9");
+}
+
+#[test]
+fn mark_completed_sends_episodes() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}, "num_episodes": 43}"#,
+        ),
+        (
+            200,
+            r#"{"status": "completed", "num_episodes_watched": 43}"#,
+        ),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.mark_completed(80)).unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Completed));
+    assert_eq!(status.num_episodes_watched, Some(43));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].method, "GET");
+    assert_eq!(requests[0].url, "/anime/80?fields=num_episodes");
+    assert_eq!(requests[1].method, "PATCH");
+    assert_eq!(requests[1].url, "/anime/80/my_list_status");
+    assert_eq!(requests[1].body, "status=completed&num_watched_episodes=43");
+}
+
+#[test]
+fn mark_completed_without_episode_count() {
+    let (url, server) = stub_server(vec![
+        (
+            200,
+            r#"{"id": 80, "title": "Mobile Suit Gundam", "main_picture": {}, "num_episodes": 0}"#,
+        ),
+        (200, r#"{"status": "completed"}"#),
+    ]);
+    let mut client = MALClient::with_access_token("token");
+    client.api_url = url;
+    let status = block_on(client.mark_completed(80)).unwrap();
+    assert_eq!(status.status, Some(WatchStatus::Completed));
+
+    let requests = server.join().unwrap();
+    assert_eq!(requests[0].url, "/anime/80?fields=num_episodes");
+    //MAL sends 0 while it doesn't know the count yet
+    assert_eq!(requests[1].body, "status=completed");
+}
+
+///Creates an empty directory in the system temp dir for a single test
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("lib-mal-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+struct StubRequest {
+    method: String,
+    url: String,
+    body: String,
+    user_agent: Option<String>,
+}
+
+///Finds a local address that nothing is listening on
+fn free_addr() -> String {
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    listener.local_addr().unwrap().to_string()
+}
+
+///Waits for the listener on `addr` to shut down, tiny_http frees the port shortly after the
+///server is dropped
+fn wait_for_port_free(addr: &str) {
+    let started = std::time::Instant::now();
+    while std::net::TcpListener::bind(addr).is_err() {
+        assert!(started.elapsed() < Duration::from_secs(5));
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
+///Plays the browser's part of the OAuth2 redirect, sending `path` to the listener at `addr` once
+///it's up and returning the raw response
+fn send_callback(addr: &str, path: &str) -> JoinHandle<String> {
+    let addr = addr.to_owned();
+    let request = format!(
+        "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+        path, addr
+    );
+    thread::spawn(move || {
+        let mut stream = loop {
+            match std::net::TcpStream::connect(&addr) {
+                Ok(stream) => break stream,
+                Err(_) => thread::sleep(Duration::from_millis(10)),
+            }
+        };
+        stream.write_all(request.as_bytes()).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    })
+}
+
+///Starts a local server that answers one request for each of the `responses`, returning its url
+///and a handle that yields the requests it received
+fn stub_server(responses: Vec<(u16, &'static str)>) -> (String, JoinHandle<Vec<StubRequest>>) {
+    stub_server_with_headers(
+        responses
+            .into_iter()
+            .map(|(status, body)| (status, vec![], body))
+            .collect(),
+    )
+}
+
+///A status, headers and body for `stub_server_with_headers` to respond with
+type StubResponse = (u16, Vec<(&'static str, &'static str)>, &'static str);
+
+///Like `stub_server`, but each response also has headers
+fn stub_server_with_headers(
+    responses: Vec<StubResponse>,
+) -> (String, JoinHandle<Vec<StubRequest>>) {
+    let server = Server::http("127.0.0.1:0").unwrap();
+    let url = format!("http://{}", server.server_addr());
+    let handle = thread::spawn(move || {
+        let mut requests = vec![];
+        for (status, headers, body) in responses {
+            let mut req = server.recv().unwrap();
+            let mut req_body = String::new();
+            req.as_reader().read_to_string(&mut req_body).unwrap();
+            requests.push(StubRequest {
+                method: req.method().to_string(),
+                url: req.url().to_owned(),
+                body: req_body,
+                user_agent: req
+                    .headers()
+                    .iter()
+                    .find(|h| h.field.equiv("User-Agent"))
+                    .map(|h| h.value.to_string()),
+            });
+            let mut res = Response::from_string(body).with_status_code(status);
+            for (name, value) in headers {
+                res.add_header(tiny_http::Header::from_bytes(name, value).unwrap());
+            }
+            req.respond(res).unwrap();
+        }
+        requests
+    });
+    (url, handle)
+}